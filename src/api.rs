@@ -0,0 +1,162 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        StatusCode,
+    },
+    middleware::{from_fn_with_state, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use axum_macros::debug_handler;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    export::{export_history, ExportFormat},
+    logging::record_ids,
+    model::{Device, Task, TaskType},
+    state::AppState,
+    store::{Dependency, HistoryEntry, HistoryFilter, TaskRecord},
+};
+
+/// Admin API for scripts and home automation, authenticated with `Authorization: Bearer <token>`.
+pub fn routes(app_state: &Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/api/history", get(get_history))
+        .route("/api/devices", get(get_devices))
+        .route("/api/devices/:device/users/:user/tasks", post(append_task))
+        .route("/api/tasks/:task", get(get_task).delete(cancel_task))
+        .route_layer(from_fn_with_state(Arc::clone(app_state), authenticate))
+}
+
+async fn authenticate(
+    app_state: State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+
+    let authorized = match token {
+        Some(token) => app_state.is_admin_token(token)?,
+        None => false,
+    };
+    if !authorized {
+        tracing::warn!("Rejected unauthenticated request to {}", request.uri());
+        return Err(AppError::Unauthorized);
+    }
+
+    Ok(next.run(request).await)
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    #[serde(flatten)]
+    filter: HistoryFilter,
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+// Method: GET
+// Query: device, type, from, to (RFC 3339), format (json or csv)
+#[debug_handler]
+async fn get_history(
+    app_state: State<Arc<AppState>>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let entries = app_state.store().history(&query.filter)?;
+
+    let body = export_history(&entries, query.format)?;
+
+    Ok(([(CONTENT_TYPE, query.format.content_type())], body))
+}
+
+// Method: GET
+#[debug_handler]
+async fn get_devices(app_state: State<Arc<AppState>>) -> Result<Json<Vec<Device>>, AppError> {
+    Ok(Json(app_state.store().list()?))
+}
+
+#[derive(Deserialize)]
+struct AppendTaskReq {
+    #[serde(rename = "type")]
+    task_type: TaskType,
+    /// Run only once another task is reported, e.g. `{"task": "<id>", "condition": "failure"}`.
+    depends_on: Option<Dependency>,
+}
+
+// Method: POST
+// Content-Type: application/json
+#[debug_handler]
+async fn append_task(
+    app_state: State<Arc<AppState>>,
+    Path((device, user)): Path<(String, String)>,
+    Json(req): Json<AppendTaskReq>,
+) -> Result<(StatusCode, Json<Vec<Task>>), AppError> {
+    record_ids(Some(&device), Some(&user), None);
+
+    let tasks = match req.depends_on {
+        Some(ref dependency) => {
+            app_state.append_dependent_task(&device, &user, &req.task_type, dependency)?
+        }
+        None => app_state.append_task(&device, &user, &req.task_type)?,
+    };
+
+    tracing::info!(
+        "Task {} appended to {}/{} via API",
+        req.task_type,
+        device,
+        user
+    );
+
+    Ok((StatusCode::CREATED, Json(tasks)))
+}
+
+#[derive(Serialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+enum TaskInfo {
+    Pending(TaskRecord),
+    Reported(HistoryEntry),
+}
+
+// Method: GET
+#[debug_handler]
+async fn get_task(
+    app_state: State<Arc<AppState>>,
+    Path(task_id): Path<String>,
+) -> Result<Json<TaskInfo>, AppError> {
+    record_ids(None, None, Some(&task_id));
+
+    let store = app_state.store();
+
+    match store.get(&task_id) {
+        Ok(record) => Ok(Json(TaskInfo::Pending(record))),
+        Err(AppError::TaskNotFound(_)) => store
+            .reported(&task_id)?
+            .map(|entry| Json(TaskInfo::Reported(entry)))
+            .ok_or(AppError::TaskNotFound(task_id)),
+        Err(e) => Err(e),
+    }
+}
+
+// Method: DELETE
+#[debug_handler]
+async fn cancel_task(
+    app_state: State<Arc<AppState>>,
+    Path(task_id): Path<String>,
+) -> Result<Json<TaskRecord>, AppError> {
+    record_ids(None, None, Some(&task_id));
+
+    let record = app_state.store().cancel(&task_id)?;
+
+    tracing::info!("Task {} cancelled via API", task_id);
+
+    Ok(Json(record))
+}
@@ -0,0 +1,241 @@
+//! A stand-in for MAA's remote control client, so the bot can be developed without a real
+//! MAA install.
+//!
+//! It polls `/get` like MAA does, runs the received tasks with random delays and results, and
+//! reports them to `/report`. `CaptureImage` tasks are answered with a generated PNG.
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use clap::Parser;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver},
+    time::sleep,
+};
+
+#[derive(Parser, Clone)]
+#[command(about = "Simulated MAA client polling the bot")]
+struct SimCommand {
+    /// Base url of the bot's HTTP server
+    #[arg(long, default_value = "http://127.0.0.1:8080")]
+    server: String,
+    #[arg(long, default_value = "maa-sim")]
+    device: String,
+    #[arg(long, default_value = "sim-user")]
+    user: String,
+    #[arg(long, default_value_t = 1000)]
+    poll_interval_ms: u64,
+    /// Minimum time a task takes to run
+    #[arg(long, default_value_t = 500)]
+    min_delay_ms: u64,
+    /// Maximum time a task takes to run
+    #[arg(long, default_value_t = 3000)]
+    max_delay_ms: u64,
+    /// Probability for a task to be reported as failed, between 0 and 1
+    #[arg(long, default_value_t = 0.1)]
+    failure_rate: f64,
+}
+
+#[derive(Serialize)]
+struct GetTaskReq<'req> {
+    user: &'req str,
+    device: &'req str,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct Task {
+    id: String,
+    #[serde(rename = "type")]
+    task_type: String,
+}
+
+#[derive(Deserialize)]
+struct GetTaskResponse {
+    tasks: Vec<Task>,
+}
+
+#[derive(Serialize)]
+struct TaskStatus<'req> {
+    user: &'req str,
+    device: &'req str,
+    task: &'req str,
+    status: &'req str,
+    payload: String,
+}
+
+struct Simulator {
+    command: SimCommand,
+    client: reqwest::Client,
+    // id of the sequential task currently running, answered to HeartBeat
+    running: Mutex<Option<String>>,
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt().with_ansi(false).init();
+
+    let command = SimCommand::parse();
+    if command.min_delay_ms > command.max_delay_ms {
+        tracing::error!("--min-delay-ms must not be greater than --max-delay-ms");
+        return;
+    }
+
+    let simulator = Arc::new(Simulator {
+        command,
+        client: reqwest::Client::new(),
+        running: Mutex::new(None),
+    });
+
+    // like MAA, immediate tasks are handled right away and the others are run one by one
+    let (sender, receiver) = unbounded_channel();
+    tokio::spawn(Arc::clone(&simulator).run_sequential(receiver));
+
+    let mut seen = HashSet::new();
+    loop {
+        match simulator.poll().await {
+            Ok(tasks) => {
+                for task in tasks.into_iter().filter(|t| seen.insert(t.id.clone())) {
+                    tracing::info!("Received task {} ({})", task.task_type, task.id);
+                    if is_immediate(&task.task_type) {
+                        tokio::spawn(Arc::clone(&simulator).run_immediate(task));
+                    } else if sender.send(task).is_err() {
+                        tracing::error!("Task runner stopped");
+                        return;
+                    }
+                }
+            }
+            Err(e) => tracing::error!("Error polling tasks: {}", e),
+        }
+
+        sleep(Duration::from_millis(simulator.command.poll_interval_ms)).await;
+    }
+}
+
+fn is_immediate(task_type: &str) -> bool {
+    matches!(task_type, "CaptureImageNow" | "HeartBeat" | "StopTask")
+}
+
+impl Simulator {
+    async fn poll(&self) -> Result<Vec<Task>, reqwest::Error> {
+        let response = self
+            .client
+            .post(format!("{}/get", self.command.server))
+            .json(&GetTaskReq {
+                user: &self.command.user,
+                device: &self.command.device,
+            })
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<GetTaskResponse>()
+            .await?;
+
+        Ok(response.tasks)
+    }
+
+    async fn run_sequential(self: Arc<Self>, mut receiver: UnboundedReceiver<Task>) {
+        while let Some(task) = receiver.recv().await {
+            if let Ok(mut running) = self.running.lock() {
+                *running = Some(task.id.clone());
+            }
+
+            let delay = {
+                let mut rng = rand::thread_rng();
+                rng.gen_range(self.command.min_delay_ms..=self.command.max_delay_ms)
+            };
+            sleep(Duration::from_millis(delay)).await;
+
+            if let Ok(mut running) = self.running.lock() {
+                *running = None;
+            }
+
+            self.finish(&task).await;
+        }
+    }
+
+    async fn run_immediate(self: Arc<Self>, task: Task) {
+        if task.task_type == "HeartBeat" {
+            let running = self
+                .running
+                .lock()
+                .ok()
+                .and_then(|r| r.clone())
+                .unwrap_or_default();
+            self.report(&task, "SUCCESS", running).await;
+            return;
+        }
+
+        self.finish(&task).await;
+    }
+
+    async fn finish(&self, task: &Task) {
+        let failed = rand::thread_rng().gen_bool(self.command.failure_rate.clamp(0.0, 1.0));
+        let status = if failed { "FAILED" } else { "SUCCESS" };
+
+        let payload = if !failed && task.task_type.starts_with("CaptureImage") {
+            BASE64_STANDARD.encode(generate_png())
+        } else {
+            String::new()
+        };
+
+        self.report(task, status, payload).await;
+    }
+
+    async fn report(&self, task: &Task, status: &str, payload: String) {
+        tracing::info!("Reporting task {} ({}): {}", task.task_type, task.id, status);
+
+        let result = self
+            .client
+            .post(format!("{}/report", self.command.server))
+            .json(&TaskStatus {
+                user: &self.command.user,
+                device: &self.command.device,
+                task: &task.id,
+                status,
+                payload,
+            })
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+
+        if let Err(e) = result {
+            tracing::error!("Error reporting task {}: {}", task.id, e);
+        }
+    }
+}
+
+/// A small gradient image with a random tint, so consecutive screenshots are distinguishable.
+fn generate_png() -> Vec<u8> {
+    const WIDTH: u32 = 320;
+    const HEIGHT: u32 = 180;
+
+    let tint: u8 = rand::thread_rng().gen();
+    let mut pixels = Vec::with_capacity((WIDTH * HEIGHT * 3) as usize);
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            pixels.push(u8::try_from(x * 255 / WIDTH).unwrap_or(u8::MAX));
+            pixels.push(u8::try_from(y * 255 / HEIGHT).unwrap_or(u8::MAX));
+            pixels.push(tint);
+        }
+    }
+
+    let mut png = vec![];
+    let mut encoder = png::Encoder::new(&mut png, WIDTH, HEIGHT);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let result = encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels));
+    if let Err(e) = result {
+        tracing::error!("Error encoding image: {}", e);
+    }
+
+    png
+}
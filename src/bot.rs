@@ -1,44 +1,95 @@
-use std::error::{self, Error};
-use core::sync::atomic::Ordering::Acquire;
-use dptree::case;
+use std::{
+    error::{self, Error},
+    sync::Arc,
+};
+use chrono::{DateTime, Utc};
+use dptree::{
+    case,
+    di::{DependencyMap, DependencySupplier},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 use teloxide::{
     dispatching::{
-        dialogue::{self, Dialogue, InMemStorage},
+        dialogue::{self, Dialogue, InMemStorage, InMemStorageError},
         Dispatcher, UpdateFilterExt, UpdateHandler,
     },
+    payloads::{AnswerCallbackQuerySetters, EditMessageTextSetters, SendMessageSetters},
     requests::Requester,
+    Bot, RequestError,
     types::{
-        BotCommand, InlineKeyboardButton, InlineKeyboardButtonKind, InlineKeyboardMarkup, Update,
+        BotCommand, CallbackQuery, InlineKeyboardButton, InlineKeyboardButtonKind, InlineKeyboardMarkup, Update,
     },
     utils::command::BotCommands,
-    Bot,
 };
-use tracing::info;
 
-use crate::{
-    config::DeviceInfo, error::AppError, model::{Task, TaskType, User}, BOT_STATE
-};
+use crate::{error::AppError, model::TaskType, state::AppState};
 
 mod append_task;
+mod export;
 mod get_current_task;
+mod history;
+mod queue;
+mod reload;
+mod retry;
 mod screenshot_all;
 
+pub use history::HistoryFilters;
+pub use retry::retry_markup;
+
 type HandlerResult = Result<(), Box<dyn error::Error + Send + Sync>>;
 
-pub async fn setup(bot: Bot) -> Result<(),AppError> {
+/// Run the bot until `shutdown` is cancelled, letting the updates being handled finish.
+pub async fn setup(app_state: Arc<AppState>, shutdown: CancellationToken) -> Result<(),AppError> {
+    let bot = app_state.bot.clone();
+
     bot.set_my_commands(vec![
         BotCommand::new("appendtask", "Append task"),
         BotCommand::new("screenshotall", "Take screenshot for all bound devices"),
         BotCommand::new("getcurrenttask", "Get current running task"),
+        BotCommand::new("queue", "Show and reorder queued tasks"),
+        BotCommand::new("history", "Show finished tasks"),
+        BotCommand::new("export", "Export finished tasks as CSV or JSON"),
+        BotCommand::new("reload", "Reload the config file"),
     ])
     .await?;
 
-    Dispatcher::builder(bot.clone(), schema())
-        .dependencies(dptree::deps![InMemStorage::<DialogState>::new()])
-        .enable_ctrlc_handler()
-        .build()
-        .dispatch()
-        .await;
+    let error_state = Arc::clone(&app_state);
+    let mut dispatcher = Dispatcher::builder(bot.clone(), schema())
+        .dependencies(dptree::deps![
+            InMemStorage::<Dialog>::new(),
+            Arc::new(HistoryFilters::default()),
+            app_state
+        ])
+        .error_handler(Arc::new(move |e: Box<dyn Error + Send + Sync>| {
+            let state = Arc::clone(&error_state);
+            async move {
+                tracing::error!("Error handling update: {}", e);
+                if e.is::<RequestError>()
+                    || e.downcast_ref::<AppError>()
+                        .is_some_and(AppError::is_telegram_error)
+                {
+                    state.metrics().observe_telegram_error();
+                }
+            }
+        }))
+        .build();
+
+    let shutdown_token = dispatcher.shutdown_token();
+    let dispatch = dispatcher.dispatch();
+    tokio::pin!(dispatch);
+
+    tokio::select! {
+        biased;
+        () = &mut dispatch => return Ok(()),
+        () = shutdown.cancelled() => {}
+    }
+
+    tracing::info!("Stopping the bot");
+    // fails if the dispatcher did not start polling yet, it can be dropped right away then
+    if shutdown_token.shutdown().is_ok() {
+        dispatch.await;
+    }
 
     Ok(())
 }
@@ -49,10 +100,15 @@ pub enum Command {
     AppendTask,
     ScreenshotAll,
     GetCurrentTask,
+    Queue,
+    History(String),
+    Export(String),
+    Reload,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub enum DialogState {
+    #[default]
     Idle,
     StartAppendTask,
     AppendTaskToDevice { device_id: String },
@@ -61,184 +117,247 @@ pub enum DialogState {
     AppendHeartBeatTaskToDevice { device_id: String },
 }
 
-impl Default for DialogState {
-    fn default() -> Self {
-        Self::Idle
-    }
+/// A dialog state with what tells the keyboards of the current step from stale ones.
+#[derive(Clone, Default)]
+pub struct Dialog {
+    state: DialogState,
+    /// Prefixed to the callback data of the dialog keyboards, drawn anew for every dialog.
+    nonce: u32,
+    /// The dialog times out once `dialog_timeout_secs` passed since then.
+    stepped_at: Option<DateTime<Utc>>,
 }
 
-type BotDialog = Dialogue<DialogState, InMemStorage<DialogState>>;
-
-fn get_users(device_id: &str) -> Result<Vec<String>,AppError> {
-    let app_state = BOT_STATE.get().ok_or(AppError::StateNotSet)?;
+type BotDialog = Dialogue<Dialog, InMemStorage<Dialog>>;
+
+/// Start a dialog at `state`, making the keyboards of any previous one stale. Returns the nonce of
+/// the new dialog.
+async fn start_dialog(dialog: &BotDialog, state: DialogState) -> Result<u32, InMemStorageError> {
+    let nonce = rand::random();
+    dialog
+        .update(Dialog {
+            state,
+            nonce,
+            stepped_at: Some(Utc::now()),
+        })
+        .await?;
 
-    app_state.devices.read()?.get(device_id).map(|d| {
-        d.users
-            .values()
-            .map(|u| u.id.clone())
-            .collect::<Vec<String>>()
-    }).ok_or(AppError::DeviceNotFound(device_id.to_owned()))
+    Ok(nonce)
 }
 
-fn get_devices(use_name: bool) -> Result<Vec<String>,AppError> {
-    let app_state = BOT_STATE.get().ok_or(AppError::StateNotSet)?;
-
-    let devices = app_state
-        .devices
-        .read()?
-        .values()
-        .map(|d| {
-            if use_name {
-                d.name.clone()
-            } else {
-                d.id.clone()
-            }
+/// Move the dialog to its next step, restarting the timeout. Returns the nonce of the dialog.
+async fn next_step(dialog: &BotDialog, state: DialogState) -> Result<u32, InMemStorageError> {
+    let nonce = dialog.get().await?.unwrap_or_default().nonce;
+    dialog
+        .update(Dialog {
+            state,
+            nonce,
+            stepped_at: Some(Utc::now()),
         })
-        .collect();
+        .await?;
 
-    Ok(devices)
+    Ok(nonce)
 }
 
-fn get_is_single_user() -> Result<bool,AppError> {
-    let app_state = BOT_STATE.get().ok_or(AppError::StateNotSet)?;
-
-    Ok(app_state
-            .is_single_user
-            .load(Acquire))
+/// Whether the button pressed belongs to the current dialog, which did not time out.
+fn is_current_step(q: &CallbackQuery, dialog: &Dialog, app_state: &AppState) -> bool {
+    let Some((nonce, _)) = q.data.as_deref().and_then(|d| d.split_once(':')) else {
+        return false;
+    };
+    let Ok(timeout) = app_state.dialog_timeout() else {
+        return false;
+    };
+
+    u32::from_str_radix(nonce, 16) == Ok(dialog.nonce)
+        && dialog
+            .stepped_at
+            .is_some_and(|at| Utc::now() - at < timeout)
 }
 
-fn get_single_device_and_user() -> Result<(DeviceInfo, User),AppError> {
-    let app_state = BOT_STATE.get().ok_or(AppError::StateNotSet)?;
-
-    info!("App state: {:?}", app_state);
-
-    let devices = app_state.devices.read()?;
-
-    #[allow(clippy::unwrap_used)]
-    let device = devices.values().next().unwrap();
-
-    #[allow(clippy::unwrap_used)]
-    let user = device.users.values().next().unwrap().clone();
-
-    Ok((device.clone().into(), user))
+/// The callback data of the button pressed in a dialog, without the nonce.
+fn pressed(q: &CallbackQuery) -> Option<&str> {
+    q.data
+        .as_deref()
+        .and_then(|d| d.split_once(':'))
+        .map(|(_, data)| data)
 }
 
-// TODO: should this be a method of AppState?
-fn append_task(device_id: &str, user_id: &str, task: &str) -> Result<(),AppError> {
-    let app_state = BOT_STATE.get().ok_or(AppError::StateNotSet)?;
-
-    let mut devices = app_state.devices.write()?;
-
-    let mut all_states = app_state.all_tasks.write()?;
-
-    let user = devices
-        .get_mut(device_id)
-        .ok_or(AppError::DeviceNotFound(device_id.to_owned()))?
-        .users
-        .get_mut(user_id)
-        .ok_or(AppError::UserNotFound(user_id.to_owned()))?;
-
-    let task = Task::from_str(task);
-    let task_id = task.id.clone();
-    let task_type = task.task_type.clone();
-    user.tasks.push(task);
-    all_states.insert(task_id, task_type.clone());
-
-    // append an extra CaptureImage task if the task itself is not
-    if !matches!(task_type, TaskType::CaptureImage) {
-        let cap_task = Task::capture_image_task();
-        let cap_task_id = cap_task.id.clone();
-        user.tasks.push(cap_task);
-        all_states.insert(cap_task_id, TaskType::CaptureImage);
-    }
-
-    Ok(())
+fn dialog_button(nonce: u32, label: impl Into<String>, data: &str) -> InlineKeyboardButton {
+    InlineKeyboardButton::new(
+        label,
+        InlineKeyboardButtonKind::CallbackData(format!("{nonce:x}:{data}")),
+    )
 }
 
-fn get_permitted_user_id() -> Result<i64,AppError> {
-    let app_state = BOT_STATE.get().ok_or(AppError::StateNotSet)?;
-
-    Ok(app_state.tg_user_id)
-}
-
-fn get_tasks_markup() -> InlineKeyboardMarkup {
+fn get_tasks_markup(nonce: u32, back: bool) -> InlineKeyboardMarkup {
     let tasks = TaskType::get_all();
     let tasks = tasks
         .iter()
-        .map(|t| {
-            let callback_text = format!("t:{t}");
-            InlineKeyboardButton::new(t, InlineKeyboardButtonKind::CallbackData(callback_text))
-        })
+        .map(|t| dialog_button(nonce, t.clone(), &format!("t:{t}")))
         .map(|t| vec![t]);
 
-    InlineKeyboardMarkup::new(tasks)
+    InlineKeyboardMarkup::new(tasks.chain([nav_row(nonce, back)]))
 }
 
-fn get_devices_markup() -> Result<InlineKeyboardMarkup,AppError> {
-    let devices = get_devices(true);
+fn get_devices_markup(app_state: &AppState, nonce: u32) -> Result<InlineKeyboardMarkup,AppError> {
+    let mut devices = app_state.devices()?;
+    devices.sort_by(|a, b| a.name.cmp(&b.name));
 
-    let devices = devices?
+    // named after the device, but identified by its id
+    let devices = devices
         .into_iter()
-        .map(|d| {
-            let callback_text = format!("d:{d}");
-            InlineKeyboardButton::new(d, InlineKeyboardButtonKind::CallbackData(callback_text))
-        })
+        .map(|d| dialog_button(nonce, d.name, &format!("d:{}", d.id)))
         .map(|d| vec![d]);
 
-    Ok(InlineKeyboardMarkup::new(devices))
+    Ok(InlineKeyboardMarkup::new(devices.chain([nav_row(nonce, false)])))
 }
 
-fn get_users_markup(device_id: &str) -> Result<InlineKeyboardMarkup,AppError> {
-    let users = get_users(device_id);
+fn get_users_markup(
+    app_state: &AppState,
+    device_id: &str,
+    nonce: u32,
+) -> Result<InlineKeyboardMarkup, AppError> {
+    let mut users = app_state.users(device_id)?;
+    users.sort();
 
-    let users = users?
+    let users = users
         .into_iter()
-        .map(|u| {
-            let callback_text = format!("u:{u}");
-            InlineKeyboardButton::new(u, InlineKeyboardButtonKind::CallbackData(callback_text))
-        })
+        .map(|u| dialog_button(nonce, u.clone(), &format!("u:{u}")))
         .map(|u| vec![u]);
 
-    Ok(InlineKeyboardMarkup::new(users))
+    Ok(InlineKeyboardMarkup::new(users.chain([nav_row(nonce, true)])))
+}
+
+/// Back and Cancel buttons closing every step of a dialog.
+fn nav_row(nonce: u32, back: bool) -> Vec<InlineKeyboardButton> {
+    let mut row = vec![];
+    if back {
+        row.push(dialog_button(nonce, "« Back", "back"));
+    }
+    row.push(dialog_button(nonce, "Cancel", "cancel"));
+    row
+}
+
+/// Show the next step of a dialog in place of the pressed keyboard, or in a new message if it is
+/// gone. An empty markup removes the keyboard.
+async fn show_step(
+    bot: &Bot,
+    dialog: &BotDialog,
+    q: &CallbackQuery,
+    text: String,
+    markup: InlineKeyboardMarkup,
+) -> HandlerResult {
+    match q.message {
+        Some(ref message) => {
+            bot.edit_message_text(message.chat.id, message.id, text)
+                .reply_markup(markup)
+                .await?;
+        }
+        None => {
+            bot.send_message(dialog.chat_id(), text)
+                .reply_markup(markup)
+                .await?;
+        }
+    }
+
+    Ok(())
 }
 
-fn schema() -> UpdateHandler<Box<dyn Error + Send + Sync + 'static>> {
+/// Answer a press on a keyboard of a dialog that is over or timed out, removing the keyboard.
+async fn expire_menu(bot: Bot, q: CallbackQuery) -> HandlerResult {
+    bot.answer_callback_query(q.id)
+        .text("This menu expired.")
+        .await?;
+
+    if let Some(message) = q.message {
+        bot.edit_message_reply_markup(message.chat.id, message.id)
+            .await?;
+    }
+
+    Ok(())
+}
+
+pub fn schema() -> UpdateHandler<Box<dyn Error + Send + Sync + 'static>> {
     let command_handler = teloxide::filter_command::<Command, _>()
         .branch(case![Command::AppendTask].endpoint(append_task::start_append_task_dialog))
         .branch(case![Command::ScreenshotAll].endpoint(screenshot_all::take_screenshot_all))
         .branch(
             case![Command::GetCurrentTask]
                 .endpoint(get_current_task::start_get_current_task_dialog),
-        );
+        )
+        .branch(case![Command::Queue].endpoint(queue::show_queue))
+        .branch(case![Command::History(args)].endpoint(history::show_history))
+        .branch(case![Command::Export(args)].endpoint(export::export))
+        .branch(case![Command::Reload].endpoint(reload::reload));
 
     let msg_handler = Update::filter_message().branch(command_handler);
 
     let callback_handler = Update::filter_callback_query()
-        .branch(case![DialogState::StartAppendTask].endpoint(append_task::receive_device))
         .branch(
-            case![DialogState::StartAppendHeartBeatTask].endpoint(append_task::receive_device),
+            dptree::filter(|q: CallbackQuery| history::is_history_callback(&q))
+                .endpoint(history::turn_page),
         )
+        .branch(dptree::filter(|q: CallbackQuery| retry::is_retry_callback(&q)).endpoint(retry::retry))
         .branch(
-            case![DialogState::AppendTaskToDevice { device_id }]
-                .endpoint(append_task::receive_user),
+            dptree::filter(|q: CallbackQuery| queue::is_queue_callback(&q))
+                .endpoint(queue::move_task),
         )
-        .branch(
-            case![DialogState::AppendHeartBeatTaskToDevice { device_id }]
-                .endpoint(append_task::receive_user),
-        )
-        .branch(
-            case![DialogState::AppendTaskToUser { device_id, user_id }]
-                .endpoint(append_task::receive_task),
-        );
-
-    dialogue::enter::<Update, InMemStorage<DialogState>, DialogState, _>()
-            .chain(dptree::filter(|dialog: BotDialog| {
-                let permitted_user_id = get_permitted_user_id().unwrap_or(-1);
+        .branch(dialog_handler())
+        // keyboards left over from dialogs that are over
+        .branch(dptree::endpoint(expire_menu));
 
+    update_span()
+        .chain(dialogue::enter::<Update, InMemStorage<Dialog>, Dialog, _>())
+            .chain(dptree::filter(|dialog: BotDialog, app_state: Arc<AppState>| {
                 let chat_id = dialog.chat_id();
 
-                chat_id.is_user() && chat_id.0 == permitted_user_id
+                chat_id.is_user() && app_state.tg_user_id().is_ok_and(|id| chat_id.0 == id)
             }))
             .branch(msg_handler)
             .branch(callback_handler)
 }
+
+/// Buttons pressed in the current dialog.
+fn dialog_handler() -> UpdateHandler<Box<dyn Error + Send + Sync + 'static>> {
+    dptree::filter(
+        |q: CallbackQuery, dialog: Dialog, app_state: Arc<AppState>| {
+            is_current_step(&q, &dialog, &app_state)
+        },
+    )
+    .map(|dialog: Dialog| dialog.state)
+    .branch(
+        dptree::filter(|q: CallbackQuery| pressed(&q) == Some("cancel"))
+            .endpoint(append_task::cancel),
+    )
+    .branch(
+        dptree::filter(|q: CallbackQuery| pressed(&q) == Some("back"))
+            .endpoint(append_task::go_back),
+    )
+    .branch(case![DialogState::StartAppendTask].endpoint(append_task::receive_device))
+    .branch(case![DialogState::StartAppendHeartBeatTask].endpoint(append_task::receive_device))
+    .branch(
+        case![DialogState::AppendTaskToDevice { device_id }].endpoint(append_task::receive_user),
+    )
+    .branch(
+        case![DialogState::AppendHeartBeatTaskToDevice { device_id }]
+            .endpoint(append_task::receive_user),
+    )
+    .branch(
+        case![DialogState::AppendTaskToUser { device_id, user_id }]
+            .endpoint(append_task::receive_task),
+    )
+}
+
+/// Run the rest of the handlers in a span identifying the update.
+fn update_span() -> UpdateHandler<Box<dyn Error + Send + Sync + 'static>> {
+    dptree::from_fn(|deps: DependencyMap, cont| {
+        let update: Arc<Update> = deps.get();
+        let span = tracing::info_span!(
+            "update",
+            update_id = update.id,
+            chat = update.chat().map(|c| c.id.0),
+            user = update.user().map(|u| u.id.0),
+        );
+
+        cont(deps).instrument(span)
+    })
+}
@@ -1,165 +1,228 @@
+use std::sync::Arc;
+
 use teloxide::{
-    payloads::{AnswerCallbackQuerySetters, SendMessageSetters},
-    requests::{Request, Requester},
-    types::CallbackQuery,
+    payloads::SendMessageSetters,
+    requests::Requester,
+    types::{CallbackQuery, InlineKeyboardMarkup},
     Bot,
 };
 
-use crate::model::TaskType;
+use crate::{error::AppError, model::TaskType, state::AppState};
 
 use super::{
-    append_task, get_devices_markup, get_is_single_user, get_single_device_and_user,
-    get_tasks_markup, get_users_markup, BotDialog, DialogState, HandlerResult,
+    expire_menu, get_devices_markup, get_tasks_markup, get_users_markup, next_step, pressed,
+    show_step, start_dialog, BotDialog, DialogState, HandlerResult,
 };
 
-pub async fn start_append_task_dialog(bot: Bot, dialog: BotDialog) -> HandlerResult {
+pub async fn start_append_task_dialog(
+    bot: Bot,
+    dialog: BotDialog,
+    app_state: Arc<AppState>,
+) -> HandlerResult {
     // If only one user is present, no need to ask for device and user
-    if get_is_single_user()? {
-        let (device, user) = get_single_device_and_user()?;
-        dialog
-            .update(DialogState::AppendTaskToUser {
+    if app_state.is_single_user()? {
+        let (device, user) = app_state.single_device_and_user()?;
+        let nonce = start_dialog(
+            &dialog,
+            DialogState::AppendTaskToUser {
                 device_id: device.id.clone(),
                 user_id: user.id.clone(),
-            })
-            .await?;
+            },
+        )
+        .await?;
 
         bot.send_message(
             dialog.chat_id(),
             format!("Select task for device {}, user {}", device.name, user.id),
         )
-        .reply_markup(get_tasks_markup())
+        .reply_markup(get_tasks_markup(nonce, false))
         .await?;
 
         return Ok(());
     }
 
-    dialog.update(DialogState::StartAppendTask).await?;
+    let nonce = start_dialog(&dialog, DialogState::StartAppendTask).await?;
 
     let sent_msg = "Select device:".to_owned();
 
     bot.send_message(dialog.chat_id(), sent_msg)
-        .reply_markup(get_devices_markup()?)
+        .reply_markup(get_devices_markup(&app_state, nonce)?)
         .await?;
 
     Ok(())
 }
 
-pub async fn receive_device(bot: Bot, dialog: BotDialog, q: CallbackQuery) -> HandlerResult {
-    if let Some(ref device_id) = q.data {
-        if !device_id.starts_with("d:") {
-            bot.send_message(dialog.chat_id(), "Invalid device id")
-                .send()
-                .await?;
-            bot.answer_callback_query(q.id).show_alert(false).await?;
-            return Ok(());
+pub async fn receive_device(
+    bot: Bot,
+    dialog: BotDialog,
+    app_state: Arc<AppState>,
+    state: DialogState,
+    q: CallbackQuery,
+) -> HandlerResult {
+    let Some(device_id) = pressed(&q).and_then(|d| d.strip_prefix("d:")) else {
+        return expire_menu(bot, q).await;
+    };
+    let device_id = device_id.to_owned();
+
+    let next_state = match state {
+        DialogState::StartAppendTask => DialogState::AppendTaskToDevice {
+            device_id: device_id.clone(),
+        },
+        DialogState::StartAppendHeartBeatTask => DialogState::AppendHeartBeatTaskToDevice {
+            device_id: device_id.clone(),
+        },
+        DialogState::Idle
+        | DialogState::AppendTaskToDevice { .. }
+        | DialogState::AppendTaskToUser { .. }
+        | DialogState::AppendHeartBeatTaskToDevice { .. } => {
+            return expire_menu(bot, q).await;
         }
+    };
 
-        let device_id = device_id.replace("d:", "");
+    let name = device_name(&app_state, &device_id)?;
+    let nonce = next_step(&dialog, next_state).await?;
 
-        #[allow(clippy::unwrap_used)]
-        let current_state = dialog.get().await?.unwrap();
+    bot.answer_callback_query(q.id.clone()).await?;
 
-        let next_state = match current_state {
-            DialogState::StartAppendTask => DialogState::AppendTaskToDevice {
-                device_id: device_id.clone(),
-            },
-            DialogState::StartAppendHeartBeatTask => {
-                DialogState::AppendHeartBeatTaskToDevice {
-                    device_id: device_id.clone(),
-                }
-            }
-            DialogState::Idle | DialogState::AppendTaskToDevice{ .. } | DialogState::AppendTaskToUser{ .. } | DialogState::AppendHeartBeatTaskToDevice{ .. } => {
-                bot.send_message(dialog.chat_id(), "Invalid state")
-                    .send()
-                    .await?;
-                bot.answer_callback_query(q.id).show_alert(false).await?;
-                return Ok(());
-            }
-        };
-
-        dialog.update(next_state).await?;
-
-        bot.answer_callback_query(q.id).show_alert(false).await?;
-
-        bot.send_message(dialog.chat_id(), "Select user")
-            .reply_markup(get_users_markup(&device_id)?)
-            .await?;
-    }
-
-    Ok(())
+    let text = format!("Select user of device {name}:");
+    let markup = get_users_markup(&app_state, &device_id, nonce)?;
+    show_step(&bot, &dialog, &q, text, markup).await
 }
 
 pub async fn receive_user(
     bot: Bot,
     dialog: BotDialog,
+    app_state: Arc<AppState>,
+    state: DialogState,
     device_id: String,
     q: CallbackQuery,
 ) -> HandlerResult {
-    if let Some(ref user_id) = q.data {
-        if !user_id.starts_with("u:") {
-            bot.send_message(dialog.chat_id(), "Invalid user id")
-                .send()
-                .await?;
-            bot.answer_callback_query(q.id).show_alert(false).await?;
-            return Ok(());
-        }
+    let Some(user_id) = pressed(&q).and_then(|d| d.strip_prefix("u:")) else {
+        return expire_menu(bot, q).await;
+    };
+    let user_id = user_id.to_owned();
+    let name = device_name(&app_state, &device_id)?;
 
-        let user_id = user_id.replace("u:", "");
+    bot.answer_callback_query(q.id.clone()).await?;
 
-        #[allow(clippy::unwrap_used)]
-        let current_state = dialog.get().await?.unwrap();
+    if let DialogState::AppendHeartBeatTaskToDevice { .. } = state {
+        dialog.exit().await?;
 
-        if let DialogState::AppendHeartBeatTaskToDevice { .. } = current_state {
-            dialog.exit().await?;
+        app_state.append_task(&device_id, &user_id, &TaskType::HeartBeat)?;
 
-            append_task(&device_id, &user_id, &TaskType::HeartBeat.to_string())?;
+        let text = format!("Asked device {name}, user {user_id} for its current task.");
+        return show_step(&bot, &dialog, &q, text, InlineKeyboardMarkup::default()).await;
+    }
 
-            bot.answer_callback_query(q.id).show_alert(true).await?;
-            return Ok(());
-        }
+    let nonce = next_step(
+        &dialog,
+        DialogState::AppendTaskToUser {
+            device_id: device_id.clone(),
+            user_id: user_id.clone(),
+        },
+    )
+    .await?;
+
+    let text = format!("Select task for device {name}, user {user_id}");
+    show_step(&bot, &dialog, &q, text, get_tasks_markup(nonce, true)).await
+}
 
-        dialog
-            .update(DialogState::AppendTaskToUser {
-                device_id: device_id.clone(),
-                user_id: user_id.clone(),
-            })
-            .await?;
+pub async fn receive_task(
+    bot: Bot,
+    dialog: BotDialog,
+    app_state: Arc<AppState>,
+    (device_id, user_id): (String, String),
+    q: CallbackQuery,
+) -> HandlerResult {
+    let Some(Ok(task)) = pressed(&q)
+        .and_then(|d| d.strip_prefix("t:"))
+        .map(str::parse::<TaskType>)
+    else {
+        return expire_menu(bot, q).await;
+    };
 
-        bot.answer_callback_query(q.id).show_alert(false).await?;
+    dialog.exit().await?;
 
-        bot.send_message(dialog.chat_id(), "Select task")
-            .reply_markup(get_tasks_markup())
-            .await?;
-    }
+    app_state.append_task(&device_id, &user_id, &task)?;
 
-    Ok(())
+    bot.answer_callback_query(q.id.clone()).await?;
+
+    let text = format!(
+        "Task {task} added for device {}, user {user_id}.",
+        device_name(&app_state, &device_id)?
+    );
+    show_step(&bot, &dialog, &q, text, InlineKeyboardMarkup::default()).await
 }
 
-pub async fn receive_task(
+/// Return to the previous step of the dialog.
+pub async fn go_back(
     bot: Bot,
     dialog: BotDialog,
-    (device_id, user_id): (String, String),
+    app_state: Arc<AppState>,
+    state: DialogState,
     q: CallbackQuery,
 ) -> HandlerResult {
-    if let Some(ref task) = q.data {
-        if !task.starts_with("t:") {
-            bot.send_message(dialog.chat_id(), "Invalid task")
-                .send()
-                .await?;
-            bot.answer_callback_query(q.id).show_alert(false).await?;
-            return Ok(());
+    let (text, markup) = match state {
+        DialogState::AppendTaskToDevice { .. } => {
+            let nonce = next_step(&dialog, DialogState::StartAppendTask).await?;
+            (
+                "Select device:".to_owned(),
+                get_devices_markup(&app_state, nonce)?,
+            )
+        }
+        DialogState::AppendHeartBeatTaskToDevice { .. } => {
+            let nonce = next_step(&dialog, DialogState::StartAppendHeartBeatTask).await?;
+            (
+                "Select device:".to_owned(),
+                get_devices_markup(&app_state, nonce)?,
+            )
+        }
+        // the device and user were not asked for if there is a single one
+        DialogState::AppendTaskToUser { device_id, .. } if !app_state.is_single_user()? => {
+            let text = format!(
+                "Select user of device {}:",
+                device_name(&app_state, &device_id)?
+            );
+            let nonce = next_step(
+                &dialog,
+                DialogState::AppendTaskToDevice {
+                    device_id: device_id.clone(),
+                },
+            )
+            .await?;
+            (text, get_users_markup(&app_state, &device_id, nonce)?)
         }
+        DialogState::Idle
+        | DialogState::StartAppendTask
+        | DialogState::StartAppendHeartBeatTask
+        | DialogState::AppendTaskToUser { .. } => return expire_menu(bot, q).await,
+    };
 
-        let task = task.replace("t:", "");
+    bot.answer_callback_query(q.id.clone()).await?;
 
-        dialog.exit().await?;
+    show_step(&bot, &dialog, &q, text, markup).await
+}
 
-        append_task(&device_id, &user_id, &task)?;
+pub async fn cancel(bot: Bot, dialog: BotDialog, q: CallbackQuery) -> HandlerResult {
+    dialog.exit().await?;
 
-        bot.answer_callback_query(q.id).show_alert(true).await?;
+    bot.answer_callback_query(q.id.clone()).await?;
 
-        bot.send_message(dialog.chat_id(), "Task added").await?;
-    }
+    show_step(
+        &bot,
+        &dialog,
+        &q,
+        "Cancelled.".to_owned(),
+        InlineKeyboardMarkup::default(),
+    )
+    .await
+}
 
-    Ok(())
+fn device_name(app_state: &AppState, device_id: &str) -> Result<String, AppError> {
+    app_state
+        .devices()?
+        .into_iter()
+        .find(|d| d.id == device_id)
+        .map(|d| d.name)
+        .ok_or(AppError::DeviceNotFound(device_id.to_owned()))
 }
@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use teloxide::{payloads::SendDocumentSetters, requests::Requester, types::InputFile, Bot};
+
+use crate::{
+    export::{export_history, ExportFormat},
+    state::AppState,
+};
+
+use super::{history::parse_filter, BotDialog, HandlerResult};
+
+const USAGE: &str = concat!(
+    "Usage: /export [csv|json] [device=<device id or name>] [type=<task type>] ",
+    "[from=<date>] [to=<date>]"
+);
+
+pub async fn export(
+    bot: Bot,
+    dialog: BotDialog,
+    app_state: Arc<AppState>,
+    args: String,
+) -> HandlerResult {
+    // the format is optional and comes first
+    let args = args.trim();
+    let (first, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let (format, filter_args) = match first.parse::<ExportFormat>() {
+        Ok(format) => (format, rest),
+        Err(_) => (ExportFormat::default(), args),
+    };
+
+    let Ok(filter) = parse_filter(&app_state, filter_args) else {
+        bot.send_message(dialog.chat_id(), USAGE).await?;
+        return Ok(());
+    };
+
+    let entries = app_state.store().history(&filter)?;
+    if entries.is_empty() {
+        bot.send_message(dialog.chat_id(), "No task in history.")
+            .await?;
+        return Ok(());
+    }
+
+    let content = export_history(&entries, format)?;
+    let file_name = format!("history.{}", format.extension());
+
+    bot.send_document(
+        dialog.chat_id(),
+        InputFile::memory(content).file_name(file_name),
+    )
+    .caption(format!("{} tasks", entries.len()))
+    .await?;
+
+    Ok(())
+}
@@ -1,31 +1,32 @@
+use std::sync::Arc;
+
 use teloxide::{payloads::SendMessageSetters, requests::Requester, Bot};
 
-use crate::model::TaskType;
+use crate::{model::TaskType, state::AppState};
 
-use super::{
-    append_task, get_devices_markup, get_is_single_user, get_single_device_and_user, BotDialog,
-    DialogState, HandlerResult,
-};
+use super::{get_devices_markup, start_dialog, BotDialog, DialogState, HandlerResult};
 
-pub async fn start_get_current_task_dialog(bot: Bot, dialog: BotDialog) -> HandlerResult {
+pub async fn start_get_current_task_dialog(
+    bot: Bot,
+    dialog: BotDialog,
+    app_state: Arc<AppState>,
+) -> HandlerResult {
     // If only one user is present, no need to ask for device and user
-    if get_is_single_user()? {
-        let (device, user) = get_single_device_and_user()?;
+    if app_state.is_single_user()? {
+        let (device, user) = app_state.single_device_and_user()?;
         dialog.exit().await?;
 
-        append_task(&device.id, &user.id, &TaskType::HeartBeat.to_string())?;
+        app_state.append_task(&device.id, &user.id, &TaskType::HeartBeat)?;
 
         return Ok(());
     }
 
-    dialog
-        .update(DialogState::StartAppendHeartBeatTask)
-        .await?;
+    let nonce = start_dialog(&dialog, DialogState::StartAppendHeartBeatTask).await?;
 
     let sent_msg = "Select device:".to_owned();
 
     bot.send_message(dialog.chat_id(), sent_msg)
-        .reply_markup(get_devices_markup()?)
+        .reply_markup(get_devices_markup(&app_state, nonce)?)
         .await?;
 
     Ok(())
@@ -0,0 +1,230 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use teloxide::{
+    payloads::{EditMessageTextSetters, SendMessageSetters},
+    requests::Requester,
+    types::{
+        CallbackQuery, InlineKeyboardButton, InlineKeyboardButtonKind, InlineKeyboardMarkup,
+        MessageId,
+    },
+    Bot,
+};
+
+use crate::{
+    error::AppError,
+    state::AppState,
+    store::{HistoryEntry, HistoryFilter},
+};
+
+use super::{expire_menu, BotDialog, HandlerResult};
+
+const PAGE_SIZE: usize = 5;
+
+/// Number of history messages whose pages can still be turned.
+const MAX_PAGED_MESSAGES: usize = 100;
+
+const USAGE: &str =
+    "Usage: /history [device=<device id or name>] [type=<task type>] [from=<date>] [to=<date>]";
+
+/// The filter of the last history messages, which does not fit in the 64 bytes of callback data.
+#[derive(Default)]
+pub struct HistoryFilters {
+    messages: Mutex<VecDeque<(MessageId, HistoryFilter)>>,
+}
+
+impl HistoryFilters {
+    fn insert(&self, message: MessageId, filter: HistoryFilter) -> Result<(), AppError> {
+        let mut messages = self.messages.lock()?;
+        if messages.len() >= MAX_PAGED_MESSAGES {
+            messages.pop_front();
+        }
+        messages.push_back((message, filter));
+
+        Ok(())
+    }
+
+    fn get(&self, message: MessageId) -> Result<Option<HistoryFilter>, AppError> {
+        let messages = self.messages.lock()?;
+
+        Ok(messages
+            .iter()
+            .find(|&&(id, _)| id == message)
+            .map(|entry| entry.1.clone()))
+    }
+}
+
+pub async fn show_history(
+    bot: Bot,
+    dialog: BotDialog,
+    app_state: Arc<AppState>,
+    filters: Arc<HistoryFilters>,
+    args: String,
+) -> HandlerResult {
+    let Ok(filter) = parse_filter(&app_state, &args) else {
+        bot.send_message(dialog.chat_id(), USAGE).await?;
+        return Ok(());
+    };
+
+    let (text, markup) = render_page(&app_state, &filter, 0)?;
+
+    let message = bot
+        .send_message(dialog.chat_id(), text)
+        .reply_markup(markup)
+        .await?;
+    filters.insert(message.id, filter)?;
+
+    Ok(())
+}
+
+pub async fn turn_page(
+    bot: Bot,
+    app_state: Arc<AppState>,
+    filters: Arc<HistoryFilters>,
+    q: CallbackQuery,
+) -> HandlerResult {
+    // forgotten after a restart or when too many history messages followed
+    let Some(filter) = q
+        .message
+        .as_ref()
+        .map(|m| filters.get(m.id))
+        .transpose()?
+        .flatten()
+    else {
+        return expire_menu(bot, q).await;
+    };
+
+    bot.answer_callback_query(q.id).await?;
+
+    let (Some(data), Some(message)) = (q.data, q.message) else {
+        return Ok(());
+    };
+
+    // callback data is "h:<page>"
+    let page = data
+        .strip_prefix("h:")
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(0);
+
+    let (text, markup) = render_page(&app_state, &filter, page)?;
+
+    bot.edit_message_text(message.chat.id, message.id, text)
+        .reply_markup(markup)
+        .await?;
+
+    Ok(())
+}
+
+pub fn is_history_callback(q: &CallbackQuery) -> bool {
+    q.data.as_deref().is_some_and(|d| d.starts_with("h:"))
+}
+
+/// Parse `device=`, `type=`, `from=` and `to=` arguments, dates being either `YYYY-MM-DD` or
+/// RFC 3339.
+pub(super) fn parse_filter(app_state: &AppState, args: &str) -> Result<HistoryFilter, AppError> {
+    let mut filter = HistoryFilter::default();
+
+    for arg in args.split_whitespace() {
+        match arg.split_once('=') {
+            Some(("device", device)) => {
+                // accept the device name shown in the other dialogs as well as the id
+                let device_id = app_state
+                    .store()
+                    .list()?
+                    .into_iter()
+                    .find(|d| d.name == device)
+                    .map_or_else(|| device.to_owned(), |d| d.id);
+                filter.device = Some(device_id);
+            }
+            Some(("type", task_type)) => filter.task_type = Some(task_type.parse()?),
+            Some(("from", from)) => filter.from = Some(parse_time(from)?),
+            Some(("to", to)) => filter.to = Some(parse_time(to)?),
+            Some(_) | None => return Err(AppError::InvalidArgument(arg.to_owned())),
+        }
+    }
+
+    Ok(filter)
+}
+
+fn parse_time(s: &str) -> Result<DateTime<Utc>, AppError> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc());
+    }
+
+    DateTime::parse_from_rfc3339(s)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|e| AppError::InvalidArgument(format!("{s}: {e}")))
+}
+
+fn render_page(
+    app_state: &AppState,
+    filter: &HistoryFilter,
+    page: usize,
+) -> Result<(String, InlineKeyboardMarkup), AppError> {
+    let entries = app_state.store().history(filter)?;
+
+    if entries.is_empty() {
+        return Ok((
+            "No task in history.".to_owned(),
+            InlineKeyboardMarkup::default(),
+        ));
+    }
+
+    let pages = entries.len().div_ceil(PAGE_SIZE);
+    let page = page.min(pages - 1);
+
+    let mut text = format!(
+        "Task history (page {}/{}, {} tasks, {} not successful)",
+        page + 1,
+        pages,
+        entries.len(),
+        entries.iter().filter(|e| !e.status.is_success()).count()
+    );
+    for entry in entries.iter().skip(page * PAGE_SIZE).take(PAGE_SIZE) {
+        text.push_str("\n\n");
+        text.push_str(&render_entry(entry));
+    }
+
+    let mut buttons = vec![];
+    if page > 0 {
+        buttons.push(InlineKeyboardButton::new(
+            "« Previous",
+            InlineKeyboardButtonKind::CallbackData(format!("h:{}", page - 1)),
+        ));
+    }
+    if page + 1 < pages {
+        buttons.push(InlineKeyboardButton::new(
+            "Next »",
+            InlineKeyboardButtonKind::CallbackData(format!("h:{}", page + 1)),
+        ));
+    }
+
+    Ok((text, InlineKeyboardMarkup::new(vec![buttons])))
+}
+
+fn render_entry(entry: &HistoryEntry) -> String {
+    let mut lines = vec![
+        format!("{} - {}", entry.task_type, entry.status),
+        format!("Device {}, user {}", entry.device, entry.user),
+        format!(
+            "Enqueued {}, fetched {}, reported {}",
+            format_time(&entry.enqueued_at),
+            entry
+                .fetched_at
+                .as_ref()
+                .map_or("never".to_owned(), format_time),
+            format_time(&entry.reported_at)
+        ),
+    ];
+    if !entry.payload_summary.is_empty() {
+        lines.push(format!("Payload: {}", entry.payload_summary));
+    }
+    lines.join("\n")
+}
+
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S").to_string()
+}
@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use teloxide::{
+    payloads::{EditMessageTextSetters, SendMessageSetters},
+    requests::Requester,
+    types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardButtonKind, InlineKeyboardMarkup},
+    Bot,
+};
+
+use crate::{error::AppError, state::AppState, store::Move};
+
+use super::{BotDialog, HandlerResult};
+
+/// Telegram allows 100 buttons per message, 3 per task not handed to MAA yet, screenshots taken
+/// after a task left out.
+const MAX_MOVABLE_TASKS: usize = 30;
+
+pub async fn show_queue(bot: Bot, dialog: BotDialog, app_state: Arc<AppState>) -> HandlerResult {
+    let (text, markup) = render_queue(&app_state)?;
+
+    bot.send_message(dialog.chat_id(), text)
+        .reply_markup(markup)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn move_task(bot: Bot, app_state: Arc<AppState>, q: CallbackQuery) -> HandlerResult {
+    bot.answer_callback_query(q.id).await?;
+
+    let (Some(data), Some(message)) = (q.data, q.message) else {
+        return Ok(());
+    };
+
+    // callback data is "q:<u|d|f>:<task id>"
+    let mut parts = data.splitn(3, ':').skip(1);
+    let to = match parts.next() {
+        Some("u") => Move::Up,
+        Some("d") => Move::Down,
+        Some("f") => Move::Front,
+        Some(_) | None => return Ok(()),
+    };
+    let task_id = parts.next().unwrap_or_default();
+
+    match app_state.store().move_task(task_id, to) {
+        // reported, cancelled or handed to MAA meanwhile, the queue is shown as it is now
+        Ok(()) | Err(AppError::TaskNotFound(_) | AppError::InvalidArgument(_)) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let (text, markup) = render_queue(&app_state)?;
+
+    bot.edit_message_text(message.chat.id, message.id, text)
+        .reply_markup(markup)
+        .await?;
+
+    Ok(())
+}
+
+pub fn is_queue_callback(q: &CallbackQuery) -> bool {
+    q.data.as_deref().is_some_and(|d| d.starts_with("q:"))
+}
+
+/// The queue of every user, numbered, with buttons moving each task not handed to MAA yet up,
+/// down or to the front along with its screenshot.
+fn render_queue(app_state: &AppState) -> Result<(String, InlineKeyboardMarkup), AppError> {
+    let mut devices = app_state.store().list()?;
+    devices.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let mut lines = vec![];
+    let mut buttons = vec![];
+    let mut number = 0;
+    for device in devices {
+        let mut users: Vec<_> = device.users.into_values().collect();
+        users.sort_by(|a, b| a.id.cmp(&b.id));
+
+        for user in users.into_iter().filter(|u| !u.tasks.is_empty()) {
+            lines.push(format!("\n{} / {}", device.name, user.id));
+
+            for task in user.tasks {
+                number += 1;
+                let record = app_state.store().get(&task.id).ok();
+                if record.as_ref().is_some_and(|r| r.fetched_at.is_some()) {
+                    lines.push(format!("{number}. {} (handed to MAA)", task.task_type));
+                    continue;
+                }
+                lines.push(format!("{number}. {}", task.task_type));
+                // a screenshot moves along with its task
+                if record.is_some_and(|r| r.capture_of.is_some()) {
+                    continue;
+                }
+
+                if buttons.len() < MAX_MOVABLE_TASKS {
+                    buttons.push(vec![
+                        move_button(format!("{number} ↑"), "u", &task.id),
+                        move_button(format!("{number} ↓"), "d", &task.id),
+                        move_button(format!("{number} to front"), "f", &task.id),
+                    ]);
+                }
+            }
+        }
+    }
+
+    let text = if lines.is_empty() {
+        "No tasks queued.".to_owned()
+    } else {
+        format!("Queued tasks:\n{}", lines.join("\n"))
+    };
+
+    Ok((text, InlineKeyboardMarkup::new(buttons)))
+}
+
+fn move_button(label: String, to: &str, task_id: &str) -> InlineKeyboardButton {
+    InlineKeyboardButton::new(
+        label,
+        InlineKeyboardButtonKind::CallbackData(format!("q:{to}:{task_id}")),
+    )
+}
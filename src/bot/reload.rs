@@ -0,0 +1,11 @@
+use std::sync::Arc;
+
+use crate::{reload::reload_and_report, state::AppState};
+
+use super::HandlerResult;
+
+pub async fn reload(app_state: Arc<AppState>) -> HandlerResult {
+    reload_and_report(&app_state).await?;
+
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use teloxide::{
+    requests::Requester,
+    types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardButtonKind, InlineKeyboardMarkup},
+    Bot,
+};
+
+use crate::{error::AppError, state::AppState};
+
+use super::HandlerResult;
+
+/// Button under a failed task notification, queueing the task again.
+pub fn retry_markup(task_id: &str) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::new(
+        "Retry",
+        InlineKeyboardButtonKind::CallbackData(format!("r:{task_id}")),
+    )]])
+}
+
+pub fn is_retry_callback(q: &CallbackQuery) -> bool {
+    q.data.as_deref().is_some_and(|d| d.starts_with("r:"))
+}
+
+pub async fn retry(bot: Bot, app_state: Arc<AppState>, q: CallbackQuery) -> HandlerResult {
+    bot.answer_callback_query(q.id).await?;
+
+    // callback data is "r:<task id>"
+    let (Some(task_id), Some(message)) = (
+        q.data.as_deref().and_then(|d| d.strip_prefix("r:")),
+        q.message,
+    ) else {
+        return Ok(());
+    };
+
+    let retried = match app_state.store().reported(task_id)? {
+        Some(entry) => app_state
+            .retry_task(&entry)
+            .map(|tasks| tasks.map(|_| entry)),
+        None => Err(AppError::TaskNotFound(task_id.to_owned())),
+    };
+    let text = match retried {
+        Ok(Some(entry)) => {
+            tracing::info!("Task {} retried on demand", task_id);
+            format!("Task {} queued again.", entry.task_type)
+        }
+        // pressed twice, or from another client
+        Ok(None) => "The task was already queued again.".to_owned(),
+        Err(AppError::TaskNotFound(_)) => "The task is no longer in the history.".to_owned(),
+        Err(e) => return Err(e.into()),
+    };
+
+    // the button is pressed once
+    bot.edit_message_reply_markup(message.chat.id, message.id)
+        .await?;
+    bot.send_message(message.chat.id, text).await?;
+
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use std::{
+    fs::write,
+    io::{self, Write},
+    sync::Arc,
+};
+
+use teloxide::Bot;
+
+use crate::{
+    config::{AppSubcommand, Config, DEFAULT_HISTORY_LIMIT},
+    error::AppError,
+    export::export_history,
+    state::AppState,
+    store::{FileStore, HistoryFilter, TaskStore},
+};
+
+/// Run an administration command against the state file, writing its output to `out`.
+pub fn run(command: AppSubcommand, config: &Config, out: &mut dyn Write) -> Result<(), AppError> {
+    match command {
+        AppSubcommand::Serve => Err(AppError::InvalidArgument(
+            "serve is not an administration command".to_owned(),
+        )),
+        AppSubcommand::CheckConfig => {
+            let devices = config
+                .devices
+                .as_ref()
+                .map_or_else(|| "all".to_owned(), |d| d.len().to_string());
+            writeln!(out, "Config is valid, {devices} devices allowed")?;
+            Ok(())
+        }
+        AppSubcommand::ListDevices => list_devices(&open_store(config)?, out),
+        AppSubcommand::Enqueue { device, user, task } => {
+            let store = Arc::new(open_store(config)?);
+            // the bot is only used to notify reports, which does not happen here
+            let app_state = AppState::new(config, Bot::new(&config.telegram_bot_token), store);
+
+            for queued in app_state.append_task(&device, &user, &task)? {
+                writeln!(out, "Queued {} ({})", queued.task_type, queued.id)?;
+            }
+            Ok(())
+        }
+        AppSubcommand::ExportHistory {
+            format,
+            output,
+            device,
+            task_type,
+            from,
+            to,
+        } => {
+            let filter = HistoryFilter {
+                device,
+                task_type,
+                from,
+                to,
+            };
+            let entries = open_store(config)?.history(&filter)?;
+            let exported = export_history(&entries, format)?;
+
+            if let Some(output) = output {
+                write(&output, exported)?;
+                writeln!(out, "Exported {} tasks to {output}", entries.len())?;
+            } else {
+                out.write_all(&exported)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn open_store(config: &Config) -> Result<FileStore, AppError> {
+    let state_file = config.state_file.as_deref().ok_or(AppError::ConfigError(
+        "state_file is not set, there is no persisted state to work on".to_owned(),
+    ))?;
+
+    FileStore::open(
+        state_file,
+        config.history_limit.unwrap_or(DEFAULT_HISTORY_LIMIT),
+    )
+}
+
+fn list_devices(store: &FileStore, out: &mut dyn Write) -> Result<(), AppError> {
+    let mut devices = store.list()?;
+    if devices.is_empty() {
+        writeln!(out, "No device registered")?;
+        return Ok(());
+    }
+    devices.sort_by(|a, b| a.id.cmp(&b.id));
+
+    for device in devices {
+        writeln!(out, "{} ({})", device.id, device.name)?;
+
+        let mut users: Vec<_> = device.users.into_values().collect();
+        users.sort_by(|a, b| a.id.cmp(&b.id));
+        for user in users {
+            writeln!(out, "  {}: {} queued tasks", user.id, user.tasks.len())?;
+            for task in user.tasks {
+                writeln!(out, "    {} ({})", task.task_type, task.id)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Run `command` printing to standard output.
+pub fn run_to_stdout(command: AppSubcommand, config: &Config) -> Result<(), AppError> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    run(command, config, &mut out)?;
+    out.flush()?;
+
+    Ok(())
+}
@@ -1,33 +1,383 @@
-use std::fs::read_to_string;
+use std::{collections::HashSet, env::var, fs::read_to_string, path::Path};
 
+use chrono::{DateTime, Duration, Utc};
 use serde::Deserialize;
 
+use crate::{
+    error::AppError,
+    export::ExportFormat,
+    logging::{level_filter, LogFormat},
+    model::TaskType,
+};
+
+pub const DEFAULT_HISTORY_LIMIT: usize = 1000;
+
+pub const DEFAULT_DIALOG_TIMEOUT_SECS: u64 = 300;
+
+/// Longest delay before retrying a failed task, one day.
+pub const MAX_RETRY_BACKOFF_SECS: u64 = 86_400;
+
+/// Environment variables overriding the secrets of the config file.
+pub const TELEGRAM_BOT_TOKEN_VAR: &str = "MAA_TGBOT_TELEGRAM_BOT_TOKEN";
+pub const ADMIN_TOKEN_VAR: &str = "MAA_TGBOT_ADMIN_TOKEN";
+
 #[derive(clap::Parser)]
+#[command(about = "Telegram bot for MAA remote control")]
 pub struct AppCommand {
+    /// Config file in JSON, TOML (.toml) or YAML (.yaml, .yml)
     pub config_file: String,
+    /// Same as the check-config command
+    #[arg(long)]
+    pub check_config: bool,
+    #[command(subcommand)]
+    pub command: Option<AppSubcommand>,
+}
+
+impl AppCommand {
+    /// The command to run, `serve` if none is given.
+    pub fn subcommand(&self) -> AppSubcommand {
+        if self.check_config {
+            return AppSubcommand::CheckConfig;
+        }
+
+        self.command.clone().unwrap_or(AppSubcommand::Serve)
+    }
 }
 
-#[derive(Deserialize, Clone, Debug)]
+/// Commands other than `serve` work on the persisted state file and should be run while the
+/// bot is stopped, as it would overwrite their changes.
+#[derive(clap::Subcommand, Clone, Debug)]
+pub enum AppSubcommand {
+    /// Run the bot and the MAA endpoints
+    Serve,
+    /// Check the config file and exit
+    CheckConfig,
+    /// List the devices, their users and queued tasks
+    ListDevices,
+    /// Queue a task, followed by a screenshot
+    Enqueue {
+        #[arg(long)]
+        device: String,
+        #[arg(long)]
+        user: String,
+        /// Task type, e.g. LinkStart-Combat
+        #[arg(long)]
+        task: TaskType,
+    },
+    /// Write the task history as JSON or CSV
+    ExportHistory {
+        #[arg(long, default_value = "json")]
+        format: ExportFormat,
+        /// File to write, standard output if not set
+        #[arg(long)]
+        output: Option<String>,
+        #[arg(long)]
+        device: Option<String>,
+        #[arg(long = "type")]
+        task_type: Option<TaskType>,
+        /// Earliest report time, RFC 3339
+        #[arg(long)]
+        from: Option<DateTime<Utc>>,
+        /// Latest report time, RFC 3339
+        #[arg(long)]
+        to: Option<DateTime<Utc>>,
+    },
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
 pub struct Config {
     pub port: u16,
+    #[serde(default)] // may be given in the environment instead
     pub telegram_bot_token: String,
     pub telegram_user_id: i64,
     pub logging_dir: Option<String>, // will be created if not exists
+    pub log_level: Option<String>, // tracing filter directives, defaults to DEFAULT_LOG_LEVEL
+    pub log_format: Option<LogFormat>, // text or json, defaults to text
+    pub log_retention_days: Option<usize>, // daily log files are kept forever if not set
     pub devices: Option<Vec<DeviceInfo>>,
+    pub state_file: Option<String>, // state is kept in memory only if not set
+    pub task_replay_window_secs: Option<u64>, // delivered tasks are never sent again if not set
+    pub history_limit: Option<usize>, // defaults to DEFAULT_HISTORY_LIMIT
+    pub admin_token: Option<String>, // the /api endpoints are disabled if not set
+    pub webhooks: Option<Vec<WebhookConfig>>,
+    pub task_retries: Option<Vec<TaskRetryConfig>>, // failed tasks are not retried if not set
+    pub dialog_timeout_secs: Option<u64>, // defaults to DEFAULT_DIALOG_TIMEOUT_SECS
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct DeviceInfo {
     pub id: String,
     pub name: String,
 }
 
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub include_image: bool, // send screenshots base64 encoded
+}
+
+/// Automatic retry of a task type reporting anything but success.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TaskRetryConfig {
+    #[serde(rename = "type")]
+    pub task_type: TaskType,
+    pub max_attempts: u32, // including the first one
+    #[serde(default)]
+    pub backoff_secs: u64, // doubled after every attempt, up to MAX_RETRY_BACKOFF_SECS
+}
+
+impl TaskRetryConfig {
+    /// Delay before queueing the attempt after `attempt` again.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let secs = self
+            .backoff_secs
+            .saturating_mul(2_u64.saturating_pow(attempt.saturating_sub(1)))
+            .min(MAX_RETRY_BACKOFF_SECS);
+
+        Duration::try_seconds(i64::try_from(secs).unwrap_or(i64::MAX)).unwrap_or_else(Duration::max_value)
+    }
+}
+
 impl Config {
+    /// Read, parse and validate the config file, with secrets overridden by the environment.
+    pub fn load(file: &str) -> Result<Self, AppError> {
+        let content =
+            read_to_string(file).map_err(|e| AppError::ConfigError(format!("{file}: {e}")))?;
+
+        let mut config = Self::parse(file, &content)?;
+        config.override_secrets(|name| var(name).ok());
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Parse `content` in the format given by the extension of `file`, JSON by default.
+    ///
+    /// Errors are prefixed with `file:line:column`.
+    pub fn parse(file: &str, content: &str) -> Result<Self, AppError> {
+        let extension = Path::new(file)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+
+        let (location, msg) = match extension.as_deref() {
+            Some("toml") => match toml::from_str(content) {
+                Ok(config) => return Ok(config),
+                Err(e) => (
+                    e.span().map(|span| line_column(content, span.start)),
+                    e.message().to_owned(),
+                ),
+            },
+            Some("yaml" | "yml") => match serde_yaml::from_str(content) {
+                Ok(config) => return Ok(config),
+                Err(e) => (
+                    e.location().map(|l| (l.line(), l.column())),
+                    strip_location(&e.to_string()),
+                ),
+            },
+            Some(_) | None => match serde_json::from_str(content) {
+                Ok(config) => return Ok(config),
+                Err(e) => (Some((e.line(), e.column())), strip_location(&e.to_string())),
+            },
+        };
 
-    #[allow(clippy::expect_used)]
-    pub fn new(file: &str) -> Self {
-        let config_file = read_to_string(file).expect("Unable to read config file");
+        Err(AppError::ConfigError(match location {
+            Some((line, column)) => format!("{file}:{line}:{column}: {msg}"),
+            None => format!("{file}: {msg}"),
+        }))
+    }
+
+    /// Replace secrets with the environment variables set, as returned by `var`.
+    pub fn override_secrets<F>(&mut self, var: F)
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        if let Some(token) = var(TELEGRAM_BOT_TOKEN_VAR) {
+            self.telegram_bot_token = token;
+        }
+        if let Some(token) = var(ADMIN_TOKEN_VAR) {
+            self.admin_token = Some(token);
+        }
+    }
+
+    /// Check the values serde cannot, reporting every problem at once.
+    pub fn validate(&self) -> Result<(), AppError> {
+        let mut errors = vec![];
+
+        if self.telegram_bot_token.trim().is_empty() {
+            errors.push(format!(
+                "telegram_bot_token is empty, set it in the config or in {TELEGRAM_BOT_TOKEN_VAR}"
+            ));
+        }
+        if self.telegram_user_id == 0 {
+            errors.push("telegram_user_id is not set".to_owned());
+        }
+        if self.admin_token.as_ref().is_some_and(|t| t.trim().is_empty()) {
+            errors.push("admin_token is empty, remove it to disable the admin API".to_owned());
+        }
+
+        if let Err(e) = level_filter(self.log_level.as_deref()) {
+            errors.push(e.to_string());
+        }
+        if self.log_retention_days == Some(0) {
+            errors.push("log_retention_days must be at least 1".to_owned());
+        }
+        if self.dialog_timeout_secs == Some(0) {
+            errors.push("dialog_timeout_secs must be at least 1".to_owned());
+        }
+
+        let mut ids = HashSet::new();
+        for device in self.devices.iter().flatten() {
+            if device.id.is_empty() {
+                errors.push(format!("device {} has an empty id", device.name));
+            } else if !ids.insert(device.id.as_str()) {
+                errors.push(format!("duplicate device id {}", device.id));
+            }
+        }
+
+        for webhook in self.webhooks.iter().flatten() {
+            if reqwest::Url::parse(&webhook.url).is_err() {
+                errors.push(format!("invalid webhook url {}", webhook.url));
+            }
+        }
+
+        let mut retried = HashSet::new();
+        for retry in self.task_retries.iter().flatten() {
+            if retry.max_attempts == 0 {
+                errors.push(format!("max_attempts of {} must be at least 1", retry.task_type));
+            }
+            if retry.backoff_secs > MAX_RETRY_BACKOFF_SECS {
+                errors.push(format!(
+                    "backoff_secs of {} must be at most {MAX_RETRY_BACKOFF_SECS}",
+                    retry.task_type
+                ));
+            }
+            if !retried.insert(retry.task_type.to_string()) {
+                errors.push(format!("duplicate task retry for {}", retry.task_type));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::ConfigError(errors.join("\n")))
+        }
+    }
+
+    /// Human readable differences from `self` to `new`, secrets left out.
+    pub fn changes(&self, new: &Self) -> Vec<String> {
+        let mut changes = vec![];
 
-        serde_json::from_str(&config_file).expect("Unable to parse config file")
+        match (self.devices.as_ref(), new.devices.as_ref()) {
+            (None, None) => {}
+            (None, Some(_)) => changes.push("Device allow-list enabled".to_owned()),
+            (Some(_), None) => changes.push("Device allow-list disabled".to_owned()),
+            (Some(old), Some(devices)) => {
+                for device in devices {
+                    match old.iter().find(|d| d.id == device.id) {
+                        None => {
+                            changes.push(format!("Device {} ({}) allowed", device.id, device.name));
+                        }
+                        Some(previous) if previous.name != device.name => changes.push(format!(
+                            "Device {} renamed from {} to {}",
+                            device.id, previous.name, device.name
+                        )),
+                        Some(_) => {}
+                    }
+                }
+                for device in old.iter().filter(|d| !devices.iter().any(|n| n.id == d.id)) {
+                    changes.push(format!(
+                        "Device {} ({}) no longer allowed",
+                        device.id, device.name
+                    ));
+                }
+            }
+        }
+
+        if self.telegram_user_id != new.telegram_user_id {
+            changes.push(format!(
+                "Authorized Telegram user changed from {} to {}",
+                self.telegram_user_id, new.telegram_user_id
+            ));
+        }
+        if self.task_replay_window_secs != new.task_replay_window_secs {
+            changes.push(format!(
+                "Task replay window changed from {} to {}",
+                describe_secs(self.task_replay_window_secs),
+                describe_secs(new.task_replay_window_secs)
+            ));
+        }
+        if self.admin_token != new.admin_token {
+            changes.push("Admin token changed".to_owned());
+        }
+        if self.webhooks != new.webhooks {
+            changes.push(format!(
+                "Webhooks changed to: {}",
+                new.webhooks
+                    .iter()
+                    .flatten()
+                    .map(|w| w.url.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+
+        if self.task_retries != new.task_retries {
+            changes.push(format!(
+                "Task retries changed to: {}",
+                new.task_retries
+                    .iter()
+                    .flatten()
+                    .map(|r| format!("{} up to {} attempts", r.task_type, r.max_attempts))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+
+        let dialog_timeout = self.dialog_timeout_secs.unwrap_or(DEFAULT_DIALOG_TIMEOUT_SECS);
+        let new_dialog_timeout = new.dialog_timeout_secs.unwrap_or(DEFAULT_DIALOG_TIMEOUT_SECS);
+        if dialog_timeout != new_dialog_timeout {
+            changes.push(format!(
+                "Dialog timeout changed from {dialog_timeout}s to {new_dialog_timeout}s"
+            ));
+        }
+
+        let restart_only = [
+            ("port", self.port != new.port),
+            (
+                "telegram_bot_token",
+                self.telegram_bot_token != new.telegram_bot_token,
+            ),
+            ("logging_dir", self.logging_dir != new.logging_dir),
+            ("log_level", self.log_level != new.log_level),
+            ("log_format", self.log_format != new.log_format),
+            ("log_retention_days", self.log_retention_days != new.log_retention_days),
+            ("state_file", self.state_file != new.state_file),
+            ("history_limit", self.history_limit != new.history_limit),
+        ];
+        for &(name, _) in restart_only.iter().filter(|&&(_, changed)| changed) {
+            changes.push(format!("{name} changed, restart to apply"));
+        }
+
+        changes
     }
 }
+
+fn describe_secs(secs: Option<u64>) -> String {
+    secs.map_or_else(|| "none".to_owned(), |secs| format!("{secs}s"))
+}
+
+/// 1-based line and column of a byte offset.
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = content.get(..offset).unwrap_or(content);
+    let line = before.matches('\n').count() + 1;
+    let column = before.rfind('\n').map_or(before.len(), |i| before.len() - i - 1) + 1;
+
+    (line, column)
+}
+
+/// Error message without the `at line X column Y` suffix, which is reported separately.
+fn strip_location(msg: &str) -> String {
+    msg.split(" at line ").next().unwrap_or(msg).to_owned()
+}
@@ -1,6 +1,6 @@
-use std::{fmt::Display, sync::PoisonError};
+use std::{fmt::Display, io, sync::PoisonError};
 
-use axum::{http::StatusCode, response::{IntoResponse, Response}};
+use axum::{http::StatusCode, response::{IntoResponse, Response}, Json};
 use serde::Serialize;
 use teloxide::RequestError;
 
@@ -19,14 +19,97 @@ pub enum AppError {
 
     TeloxideError(String),
 
-    StateNotSet,
+    TelegramRetryAfter(u64),
+
+    TelegramNetworkError(String),
+
+    NoDeviceRegistered,
+
+    PersistError(String),
+
+    InvalidTaskType(String),
+
+    InvalidArgument(String),
+
+    ExportError(String),
+
+    Unauthorized,
+
+    Forbidden(String),
+
+    WebhookError(String),
+
+    MetricsError(String),
+
+    ConfigError(String),
+
+    ServerError(String),
 }
 
 impl From<RequestError> for AppError {
 
     fn from(e: RequestError) -> Self {
 
-        Self::TeloxideError(e.to_string())
+        match e {
+            RequestError::RetryAfter(after) => Self::TelegramRetryAfter(after.as_secs()),
+            RequestError::Network(ref source) => Self::TelegramNetworkError(source.to_string()),
+            RequestError::Api(_)
+            | RequestError::MigrateToChatId(_)
+            | RequestError::InvalidJson { .. }
+            | RequestError::Io(_) => Self::TeloxideError(e.to_string()),
+        }
+    }
+}
+
+impl AppError {
+    /// Whether the error comes from a Telegram Bot API request.
+    pub fn is_telegram_error(&self) -> bool {
+        matches!(
+            *self,
+            AppError::TeloxideError(_)
+                | AppError::TelegramRetryAfter(_)
+                | AppError::TelegramNetworkError(_)
+        )
+    }
+}
+
+impl From<reqwest::Error> for AppError {
+
+    fn from(e: reqwest::Error) -> Self {
+
+        Self::WebhookError(e.to_string())
+    }
+}
+
+impl From<prometheus::Error> for AppError {
+
+    fn from(e: prometheus::Error) -> Self {
+
+        Self::MetricsError(e.to_string())
+    }
+}
+
+impl From<io::Error> for AppError {
+
+    fn from(e: io::Error) -> Self {
+
+        Self::PersistError(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+
+    fn from(e: serde_json::Error) -> Self {
+
+        Self::PersistError(e.to_string())
+    }
+}
+
+impl From<csv::Error> for AppError {
+
+    fn from(e: csv::Error) -> Self {
+
+        Self::ExportError(e.to_string())
     }
 }
 
@@ -44,7 +127,27 @@ impl IntoResponse for AppError {
 
         tracing::error!("AppError: {}", self);
 
-        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        let status = match self {
+            AppError::DeviceNotFound(_)
+            | AppError::UserNotFound(_)
+            | AppError::TaskNotFound(_)
+            | AppError::NoDeviceRegistered => StatusCode::NOT_FOUND,
+            AppError::InvalidTaskType(_) | AppError::InvalidArgument(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::PoisonError(_)
+            | AppError::TeloxideError(_)
+            | AppError::TelegramRetryAfter(_)
+            | AppError::TelegramNetworkError(_)
+            | AppError::PersistError(_)
+            | AppError::ExportError(_)
+            | AppError::WebhookError(_)
+            | AppError::MetricsError(_)
+            | AppError::ConfigError(_)
+            | AppError::ServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        (status, Json(serde_json::json!({ "error": self.to_string() }))).into_response()
     }
 }
 
@@ -59,7 +162,19 @@ impl Display for AppError {
             AppError::PoisonError(ref e) => write!(f, "PoisonError: {e}"),
             AppError::TaskNotFound(ref e) => write!(f, "Task not found with id: {e}"),
             AppError::TeloxideError(ref e) => write!(f, "TeloxideError: {e}"),
-            AppError::StateNotSet => write!(f, "State not set"),
+            AppError::TelegramRetryAfter(e) => write!(f, "Telegram asks to retry after {e}s"),
+            AppError::TelegramNetworkError(ref e) => write!(f, "TelegramNetworkError: {e}"),
+            AppError::NoDeviceRegistered => write!(f, "No device registered"),
+            AppError::PersistError(ref e) => write!(f, "PersistError: {e}"),
+            AppError::InvalidTaskType(ref e) => write!(f, "Invalid task type: {e}"),
+            AppError::InvalidArgument(ref e) => write!(f, "Invalid argument: {e}"),
+            AppError::ExportError(ref e) => write!(f, "ExportError: {e}"),
+            AppError::Unauthorized => write!(f, "Unauthorized"),
+            AppError::Forbidden(ref e) => write!(f, "Forbidden: {e}"),
+            AppError::WebhookError(ref e) => write!(f, "WebhookError: {e}"),
+            AppError::MetricsError(ref e) => write!(f, "MetricsError: {e}"),
+            AppError::ConfigError(ref e) => write!(f, "ConfigError: {e}"),
+            AppError::ServerError(ref e) => write!(f, "ServerError: {e}"),
         }
     }
 }
@@ -0,0 +1,57 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::{error::AppError, store::HistoryEntry};
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    #[default]
+    Json,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Json => "application/json",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            _ => Err(AppError::InvalidArgument(s.to_owned())),
+        }
+    }
+}
+
+/// Serialize history entries, one row or array element per entry.
+pub fn export_history(entries: &[HistoryEntry], format: ExportFormat) -> Result<Vec<u8>, AppError> {
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            for entry in entries {
+                writer.serialize(entry)?;
+            }
+            writer
+                .into_inner()
+                .map_err(|e| AppError::ExportError(e.to_string()))
+        }
+        ExportFormat::Json => Ok(serde_json::to_vec_pretty(entries)?),
+    }
+}
@@ -0,0 +1,121 @@
+use std::{sync::Arc, time::Duration as StdDuration};
+
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use axum_macros::debug_handler;
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use teloxide::requests::Requester;
+use tokio::time::interval;
+
+use crate::state::AppState;
+
+/// How often the Telegram Bot API is checked with `get_me`.
+const TELEGRAM_CHECK_INTERVAL_SECS: u64 = 60;
+
+/// The bot is not ready when `get_me` has not succeeded for this long.
+const TELEGRAM_CHECK_MAX_AGE_SECS: i64 = 3 * 60;
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+}
+
+/// Call `get_me` periodically, recording successes for `/readyz`.
+pub async fn check_telegram(app_state: Arc<AppState>) {
+    let mut interval = interval(StdDuration::from_secs(TELEGRAM_CHECK_INTERVAL_SECS));
+
+    loop {
+        interval.tick().await;
+        check_telegram_once(&app_state).await;
+    }
+}
+
+pub async fn check_telegram_once(app_state: &AppState) {
+    match app_state.bot.get_me().await {
+        Ok(_) => app_state.set_telegram_checked_at(Utc::now()),
+        Err(e) => {
+            tracing::warn!("Telegram health check failed: {}", e);
+            app_state.metrics().observe_telegram_error();
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl Check {
+    fn ok() -> Self {
+        Self {
+            ok: true,
+            detail: None,
+        }
+    }
+
+    fn failed(detail: String) -> Self {
+        Self {
+            ok: false,
+            detail: Some(detail),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+    state: Check,
+    telegram: Check,
+    store: Check,
+}
+
+// Method: GET
+#[debug_handler]
+async fn healthz() -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "status": "ok" }))
+}
+
+// Method: GET
+#[debug_handler]
+async fn readyz(app_state: State<Arc<AppState>>) -> (StatusCode, Json<Readiness>) {
+    // handlers only run once the state is built, it is reported for the supervisor's sake
+    let state = Check::ok();
+
+    let telegram = match app_state.telegram_checked_at() {
+        Some(checked_at)
+            if Utc::now() - checked_at < Duration::seconds(TELEGRAM_CHECK_MAX_AGE_SECS) =>
+        {
+            Check::ok()
+        }
+        Some(checked_at) => Check::failed(format!(
+            "get_me last succeeded at {}",
+            checked_at.to_rfc3339()
+        )),
+        None => Check::failed("get_me has not succeeded yet".to_owned()),
+    };
+
+    let store = match app_state.store().list() {
+        Ok(_) => Check::ok(),
+        Err(e) => Check::failed(e.to_string()),
+    };
+
+    let ready = state.ok && telegram.ok && store.ok;
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status,
+        Json(Readiness {
+            ready,
+            state,
+            telegram,
+            store,
+        }),
+    )
+}
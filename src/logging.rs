@@ -0,0 +1,129 @@
+use std::{fs::create_dir_all, io::stdout};
+
+use axum::{body::Body, http::Request};
+use serde::Deserialize;
+use tower_http::{
+    classify::{ServerErrorsAsFailures, SharedClassifier},
+    trace::{MakeSpan, TraceLayer},
+};
+use tracing::{field::Empty, Span};
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{
+    fmt::{self, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
+
+use crate::{config::Config, error::AppError};
+
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// The log filter from `RUST_LOG`, or `log_level` of the config if not set.
+fn filter(config: &Config) -> Result<EnvFilter, AppError> {
+    if let Ok(filter) = EnvFilter::try_from_default_env() {
+        return Ok(filter);
+    }
+
+    level_filter(config.log_level.as_deref())
+}
+
+/// Parse `log_level` filter directives, e.g. `info` or `warn,maa_telegram_bot=debug`.
+pub fn level_filter(level: Option<&str>) -> Result<EnvFilter, AppError> {
+    let level = level.unwrap_or(DEFAULT_LOG_LEVEL);
+
+    EnvFilter::try_new(level).map_err(|e| AppError::ConfigError(format!("log_level {level}: {e}")))
+}
+
+/// Set up the global subscriber as configured.
+///
+/// The returned guard flushes the log file when dropped, it must be kept until exiting.
+pub fn init(config: &Config) -> Result<Option<WorkerGuard>, AppError> {
+    let (writer, guard) = if let Some(ref logging_dir) = config.logging_dir {
+        create_dir_all(logging_dir)?;
+
+        let mut appender = RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix("maa-tgbot.log");
+        if let Some(retention_days) = config.log_retention_days {
+            appender = appender.max_log_files(retention_days);
+        }
+        let appender = appender
+            .build(logging_dir)
+            .map_err(|e| AppError::PersistError(e.to_string()))?;
+
+        let (non_blocking, guard) = tracing_appender::non_blocking(appender);
+        (BoxMakeWriter::new(non_blocking), Some(guard))
+    } else {
+        (BoxMakeWriter::new(stdout), None)
+    };
+
+    let layer = match config.log_format.unwrap_or_default() {
+        LogFormat::Text => fmt::layer().with_ansi(false).with_writer(writer).boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_writer(writer)
+            .boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(layer)
+        .with(filter(config)?)
+        .try_init()
+        .map_err(|e| AppError::ConfigError(e.to_string()))?;
+
+    if let Some(ref logging_dir) = config.logging_dir {
+        tracing::info!("Logging to: {}", logging_dir);
+    }
+
+    Ok(guard)
+}
+
+/// Span of an HTTP request, with the ids it is about filled in by the handlers.
+#[derive(Clone, Copy, Debug)]
+pub struct RequestSpan;
+
+impl MakeSpan<Body> for RequestSpan {
+    fn make_span(&mut self, request: &Request<Body>) -> Span {
+        tracing::info_span!(
+            "request",
+            method = %request.method(),
+            uri = %request.uri(),
+            device = Empty,
+            user = Empty,
+            task = Empty,
+        )
+    }
+}
+
+pub fn request_trace_layer() -> TraceLayer<SharedClassifier<ServerErrorsAsFailures>, RequestSpan> {
+    TraceLayer::new_for_http().make_span_with(RequestSpan)
+}
+
+/// Record the device, user and task a request is about in the current request span.
+pub fn record_ids(device: Option<&str>, user: Option<&str>, task: Option<&str>) {
+    let span = Span::current();
+
+    if let Some(device) = device {
+        span.record("device", device);
+    }
+    if let Some(user) = user {
+        span.record("user", user);
+    }
+    if let Some(task) = task {
+        span.record("task", task);
+    }
+}
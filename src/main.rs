@@ -1,133 +1,191 @@
-use std::{
-    collections::HashMap, fs::create_dir_all, net::SocketAddr, process::exit, sync::{atomic::{AtomicBool, Ordering}, Arc, RwLock}
-};
+use std::{io::stderr, net::SocketAddr, process::ExitCode, sync::Arc};
 
 use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
 use axum_macros::debug_handler;
 use base64::{prelude::BASE64_STANDARD, Engine};
 use clap::Parser;
-use config::{AppCommand, DeviceInfo};
+use config::{AppCommand, AppSubcommand, DEFAULT_HISTORY_LIMIT};
 use error::AppError;
-use model::{Device, GetTaskReq, GetTaskResponse, TaskStatus, TaskType};
-use once_cell::sync::OnceCell;
-use teloxide::{
-    requests::{Request, Requester},
-    types::{ChatId, InputFile},
-    Bot,
+use model::{GetTaskReq, GetTaskResponse, ReportStatus, TaskStatus, TaskType};
+use tokio::{net::TcpListener, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    config::Config,
+    notify::{RunningTask, TaskReport},
+    outbox::RetryPolicy,
+    state::AppState,
+    store::{FileStore, HistoryEntry, MemoryStore, Reported, TaskStore},
 };
-use tokio::net::TcpListener;
-use tracing_appender::rolling::daily;
-
-use crate::{config::Config, model::User};
 
+mod api;
 mod bot;
+mod cli;
 mod config;
+mod export;
+mod health;
+mod logging;
+mod metrics;
 mod model;
 mod error;
+mod notify;
+mod outbox;
+mod reload;
+mod shutdown;
+mod state;
+mod store;
+#[cfg(test)]
+mod tests;
 
-static BOT_STATE: OnceCell<Arc<AppState>> = OnceCell::new();
-
-#[derive(Debug)]
-struct AppState {
-    pub devices: Arc<RwLock<HashMap<String, Device>>>,
-    pub all_tasks: Arc<RwLock<HashMap<String, TaskType>>>,
-    pub tg_user_id: i64,
-    pub bot: Bot,
-    pub is_single_user: AtomicBool,
-    pub allowed_devices: Option<HashMap<String, String>>,
-}
-
-impl AppState {
-    pub fn new(tg_user_id: i64, bot: Bot, allowed_devices: &Option<Vec<DeviceInfo>>) -> Self {
-        let allowed_devices = allowed_devices.clone().map(|devices| {
-            devices
-                .iter()
-                .map(|device| (device.id.clone(), device.name.clone()))
-                .collect()
-        });
-
-        Self {
-            devices: Arc::new(RwLock::new(HashMap::new())),
-            all_tasks: Arc::new(RwLock::new(HashMap::new())),
-            tg_user_id,
-            bot,
-            is_single_user: AtomicBool::new(false),
-            allowed_devices,
+#[tokio::main]
+async fn main() -> ExitCode {
+    let command = AppCommand::parse();
+    let subcommand = command.subcommand();
+
+    let config = match Config::load(&command.config_file) {
+        Ok(config) => config,
+        Err(e) => {
+            // the logging dir is unknown without a config
+            tracing_subscriber::fmt().with_ansi(false).init();
+            tracing::error!("Invalid config: {}", e);
+            return ExitCode::FAILURE;
         }
+    };
+
+    if matches!(subcommand, AppSubcommand::Serve) {
+        return serve(config, &command.config_file).await;
     }
-}
 
-#[tokio::main]
-async fn main() {
-    let config = AppCommand::parse();
-    let config = Config::new(&config.config_file);
-
-    if let Some(ref logging_dir) = config.logging_dir {
-        if let Err(e) = create_dir_all(logging_dir){
-            tracing::error!("Error creating logging dir: {}", e);
-        };
-        let file_appender = daily(logging_dir, "maa-tgbot.log");
-        let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
-        tracing_subscriber::fmt()
-            .pretty()
-            .with_ansi(false)
-            .with_writer(non_blocking)
-            .init();
-        tracing::info!("Logging to: {}", logging_dir);
-    } else {
-        tracing_subscriber::fmt().with_ansi(false).init();
+    // keep standard output for the command's own output
+    tracing_subscriber::fmt()
+        .with_ansi(false)
+        .with_writer(stderr)
+        .init();
+    if let Err(e) = cli::run_to_stdout(subcommand, &config) {
+        tracing::error!("{}", e);
+        return ExitCode::FAILURE;
     }
 
-    let bot = teloxide::Bot::new(config.telegram_bot_token);
+    ExitCode::SUCCESS
+}
 
-    let bot_clone = bot.clone();
+async fn serve(config: Config, config_file: &str) -> ExitCode {
+    // flushes the log file when dropped, after everything else
+    let _log_guard = match logging::init(&config) {
+        Ok(guard) => guard,
+        Err(e) => {
+            tracing_subscriber::fmt().with_ansi(false).init();
+            tracing::error!("Error setting up logging: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
 
-    let allowed_devices = config.devices;
+    match run(config, config_file).await {
+        Ok(()) => {
+            tracing::info!("Shut down");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            tracing::error!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    let app_state = Arc::new(AppState::new(
-        config.telegram_user_id,
-        bot,
-        &allowed_devices,
-    ));
+/// Serve MAA and run the bot until SIGINT or SIGTERM, then stop accepting requests, let the
+/// reports and updates being handled finish, send the queued notifications and flush the state.
+async fn run(config: Config, config_file: &str) -> Result<(), AppError> {
+    let bot = teloxide::Bot::new(&config.telegram_bot_token);
+
+    let history_limit = config.history_limit.unwrap_or(DEFAULT_HISTORY_LIMIT);
+    let store: Arc<dyn TaskStore> = if let Some(ref state_file) = config.state_file {
+        let store = FileStore::open(state_file, history_limit).map_err(|e| {
+            AppError::PersistError(format!("opening state file {state_file}: {e}"))
+        })?;
+        tracing::info!("Persisting state to: {}", state_file);
+        Arc::new(store)
+    } else {
+        Arc::new(MemoryStore::new(history_limit))
+    };
 
-    let app = Router::new()
-        .route("/report", post(report_status))
-        .route("/get", post(get_task))
-        .with_state(Arc::clone(&app_state));
+    let app_state =
+        Arc::new(AppState::new(&config, bot, store).with_config_file(config_file));
 
-    if let Err(_e) = BOT_STATE.set(Arc::clone(&app_state)) {
-        tracing::error!("Error setting BOT_STATE");
-        exit(1);
-    }
+    let app = router(Arc::clone(&app_state));
 
     let address = SocketAddr::from(([127, 0, 0, 1], config.port));
 
     let listener = TcpListener::bind(&address)
         .await
-        .unwrap_or_else(|e| {
-            tracing::error!("Error binding to {}: {}", address, e);
-            exit(1);
-        });
+        .map_err(|e| AppError::ServerError(format!("binding to {address}: {e}")))?;
 
+    let shutdown = CancellationToken::new();
+    let signal_shutdown = shutdown.clone();
     tokio::spawn(async move {
-        bot::setup(bot_clone).await.unwrap_or_else(|e| {
-            tracing::error!("Error setting up bot: {}", e);
-            exit(1);
-        });
+        shutdown::signal_received().await;
+        signal_shutdown.cancel();
     });
 
-    if let Err(e) = axum::serve(listener, app).await {
-        tracing::error!("Error serving: {}", e);
-        exit(1);
-    }
+    tokio::spawn(health::check_telegram(Arc::clone(&app_state)));
+    #[cfg(unix)]
+    tokio::spawn(reload::watch_sighup(Arc::clone(&app_state)));
+
+    // whichever stops first, on a signal or an error, takes the other one down with it
+    let bot_run = async {
+        // the dispatcher makes for a large future, keep it on the heap
+        let result = Box::pin(bot::setup(Arc::clone(&app_state), shutdown.clone())).await;
+        shutdown.cancel();
+        tracing::info!("Bot stopped");
+        result
+    };
+    let maa_server = async {
+        let result = axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown.clone().cancelled_owned())
+            .await
+            .map_err(|e| AppError::ServerError(e.to_string()));
+        shutdown.cancel();
+        tracing::info!("Stopped serving MAA");
+        result
+    };
+    // keeps going until the server stopped taking reports
+    let outbox_shutdown = CancellationToken::new();
+    let outbox_run = tokio::spawn(outbox::run(
+        Arc::clone(&app_state),
+        RetryPolicy::default(),
+        outbox_shutdown.clone(),
+    ));
+
+    let (server_result, bot_result) = tokio::join!(maa_server, bot_run);
+
+    drain(&app_state, &outbox_shutdown, outbox_run).await?;
+
+    server_result.and(bot_result)
 }
 
-fn get_task_type(app_state: &Arc<AppState>, task_id: &str) -> Result<TaskType,AppError> {
-    let all_tasks = app_state.all_tasks.read()?;
+/// Deliver the notifications left once the server stopped taking reports, then persist the
+/// state.
+async fn drain(
+    app_state: &AppState,
+    outbox_shutdown: &CancellationToken,
+    outbox_run: JoinHandle<()>,
+) -> Result<(), AppError> {
+    outbox_shutdown.cancel();
+    if let Err(e) = outbox_run.await {
+        tracing::error!("Error delivering notifications: {}", e);
+    }
 
-    let task_type = all_tasks.get(task_id).ok_or(AppError::TaskNotFound(task_id.to_owned()))?;
+    app_state.store().flush()
+}
 
-    Ok(task_type.clone())
+fn router(app_state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/report", post(report_status))
+        .route("/get", post(get_task))
+        .merge(api::routes(&app_state))
+        .merge(metrics::routes())
+        .merge(health::routes())
+        .layer(logging::request_trace_layer())
+        .with_state(app_state)
 }
 
 // Method: POST
@@ -137,56 +195,48 @@ async fn report_status(
     app_state: State<Arc<AppState>>,
     Json(req): Json<TaskStatus>,
 ) -> Result<StatusCode, AppError> {
-    tracing::info!("Report status");
-
-    let task_type = get_task_type(&app_state, &req.task)?;
+    logging::record_ids(Some(&req.device), Some(&req.user), Some(&req.task));
+    tracing::info!("Report status {}", req.status);
 
-    let notify_msg = format!("Task {} finished. Status: {}", task_type, req.status);
-
-    app_state
-        .bot
-        .send_message(ChatId(app_state.tg_user_id), notify_msg)
-        .send()
-        .await?;
+    let record = match app_state.store().get(&req.task) {
+        Ok(record) => {
+            check_owner(&app_state, &req, &record.device, &record.user)?;
+            record
+        }
+        Err(AppError::TaskNotFound(task_id)) => {
+            // MAA retries reports it got no response for
+            let previous = app_state
+                .store()
+                .reported(&task_id)?
+                .ok_or(AppError::TaskNotFound(task_id))?;
+            check_owner(&app_state, &req, &previous.device, &previous.user)?;
+            let payload_summary = req.payload_summary(&previous.task_type);
+            return Ok(report_again(&previous, &req.status, &payload_summary));
+        }
+        Err(e) => return Err(e),
+    };
+    let task_type = record.task.task_type.clone();
 
-    // handle and send payload
+    // handle payload
+    let mut image = None;
+    let mut running_task = None;
     match task_type {
         TaskType::CaptureImage | TaskType::CaptureImageNow => {
-            let payload = req.payload;
-            // convert base64 image to bytes
-            #[allow(clippy::unwrap_used)]
-            let payload = BASE64_STANDARD.decode(payload.as_bytes()).unwrap();
-
-            let photo = InputFile::memory(payload);
-
-            app_state
-                .bot
-                .send_photo(ChatId(app_state.tg_user_id), photo)
-                .send()
-                .await?;
+            // a failed capture comes without image
+            if !req.payload.is_empty() {
+                let payload = BASE64_STANDARD
+                    .decode(req.payload.as_bytes())
+                    .map_err(|e| AppError::InvalidArgument(format!("payload: {e}")))?;
+                image = Some(payload);
+            }
         }
         TaskType::HeartBeat => {
-            let payload = req.payload;
-
-            if payload.is_empty() {
-                app_state
-                    .bot
-                    .send_message(ChatId(app_state.tg_user_id), "No task is running.")
-                    .await?;
-                return Ok(StatusCode::OK);
+            if !req.payload.is_empty() {
+                running_task = Some(RunningTask {
+                    id: req.payload.clone(),
+                    task_type: app_state.task_type(&req.payload)?,
+                });
             }
-
-            let response_task_type = get_task_type(&app_state, &payload)?;
-
-            let msg = format!("Task {response_task_type} is running.\nTask id: {payload}");
-
-            app_state
-                .bot
-                .send_message(ChatId(app_state.tg_user_id), msg)
-                .send()
-                .await?;
-
-            return Ok(StatusCode::OK);
         }
         TaskType::LinkStartCombat
         | TaskType::LinkStartBase
@@ -195,12 +245,86 @@ async fn report_status(
         | TaskType::LinkStartMission
         | TaskType::LinkStartAutoRoguelike
         | TaskType::LinkStartReclamationAlgorithm
-        | TaskType::LinkStartRecruiting => {}
+        | TaskType::LinkStartRecruiting
+        | TaskType::StopTask => {}
+    }
+
+    let payload_summary = req.payload_summary(&task_type);
+    let (reported, attempt) = app_state.mark_reported(&record, &req.status, &payload_summary)?;
+    let entry = match reported {
+        Reported::First(entry) => entry,
+        Reported::Again(previous) => {
+            return Ok(report_again(&previous, &req.status, &payload_summary))
+        }
+    };
+    app_state.metrics().observe_reported(&entry);
+
+    let report = TaskReport {
+        task_id: entry.task_id,
+        task_type,
+        device: entry.device,
+        user: entry.user,
+        status: entry.status,
+        image,
+        running_task,
+        attempt,
+    };
+
+    // MAA would retry a failed report, so notification errors are only logged
+    if let Err(e) = app_state.notify(report) {
+        tracing::error!("{}", e);
     }
 
     Ok(StatusCode::OK)
 }
 
+/// Make sure a report comes from the device and user the task was queued for.
+///
+/// Fails with not found if the reporting device or user is unknown, and forbidden otherwise.
+fn check_owner(
+    app_state: &AppState,
+    req: &TaskStatus,
+    device: &str,
+    user: &str,
+) -> Result<(), AppError> {
+    if req.device == device && req.user == user {
+        return Ok(());
+    }
+
+    tracing::warn!(
+        "Rejected report of task {} queued for {}/{}",
+        req.task,
+        device,
+        user
+    );
+
+    if !app_state.users(&req.device)?.contains(&req.user) {
+        return Err(AppError::UserNotFound(req.user.clone()));
+    }
+
+    Err(AppError::Forbidden(format!(
+        "task {} was not queued for {}/{}",
+        req.task, req.device, req.user
+    )))
+}
+
+/// Accept a report of a task reported before without notifying it again, warning if it
+/// differs from the first one, which is kept.
+fn report_again(previous: &HistoryEntry, status: &ReportStatus, payload_summary: &str) -> StatusCode {
+    if previous.status == *status && previous.payload_summary == payload_summary {
+        tracing::info!("Ignoring duplicate report of task {}", previous.task_id);
+    } else {
+        tracing::warn!(
+            "Task {} reported again with status {}, keeping the first report with status {}",
+            previous.task_id,
+            status,
+            previous.status
+        );
+    }
+
+    StatusCode::OK
+}
+
 // Method: POST
 // Content-Type: application/json
 #[debug_handler]
@@ -208,44 +332,9 @@ async fn get_task(
     app_state: State<Arc<AppState>>,
     Json(req): Json<GetTaskReq>,
 ) -> Result<Json<GetTaskResponse>, AppError> {
-    let mut devices = app_state.devices.write()?;
-
-    if !devices.contains_key(&req.device) {
-        if let Some(ref allowed_devices) = app_state.allowed_devices {
-            if allowed_devices.contains_key(&req.device) {
-                let device_name = allowed_devices.get(&req.device).ok_or(AppError::DeviceNotFound(req.device.clone()))?;
-                tracing::info!("New allowed device: {} ({})", req.device, device_name);
-                devices.insert(
-                    req.device.clone(),
-                    Device::new_with_name(req.device.clone(), device_name.clone()),
-                );
-            } else {
-                return Ok(Json(GetTaskResponse { tasks: vec![] }));
-            }
-        } else {
-            tracing::info!("New device: {}", req.device);
-            devices.insert(req.device.clone(), Device::new(&req.device));
-        }
-    }
+    logging::record_ids(Some(&req.device), Some(&req.user), None);
 
-    let device_length = devices.len();
-    let device = devices.get_mut(&req.device).ok_or(AppError::DeviceNotFound(req.device))?;
-    let users = &mut device.users;
-    let user = users.entry(req.user.clone()).or_insert(User {
-        id: req.user.clone(),
-        tasks: vec![],
-    });
-    let tasks = user.tasks.clone();
-
-    if device_length == 1 && users.len() == 1 {
-        app_state
-            .is_single_user
-            .store(true, Ordering::Release);
-    } else {
-        app_state
-            .is_single_user
-            .store(false, Ordering::Release);
-    }
+    let tasks = app_state.poll(&req.device, &req.user)?;
 
     Ok(Json(GetTaskResponse { tasks }))
 }
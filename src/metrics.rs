@@ -0,0 +1,208 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::State, http::header::CONTENT_TYPE, response::IntoResponse, routing::get, Router,
+};
+use axum_macros::debug_handler;
+use chrono::{DateTime, Duration, Utc};
+use prometheus::{
+    core::Collector, exponential_buckets, Encoder, HistogramOpts, HistogramVec, IntCounter,
+    IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+use crate::{
+    error::AppError,
+    model::{Device, TaskType},
+    state::AppState,
+    store::HistoryEntry,
+};
+
+/// A device is reported offline when it has not polled `/get` for this long.
+const ONLINE_TIMEOUT_SECS: i64 = 60;
+
+/// Prometheus metrics of the bot, exposed on `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    polls: IntCounterVec,
+    tasks_enqueued: IntCounterVec,
+    tasks_reported: IntCounterVec,
+    report_latency: HistogramVec,
+    run_time: HistogramVec,
+    telegram_errors: IntCounter,
+    queue_length: IntGaugeVec,
+    device_online: IntGaugeVec,
+    last_polls: Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+impl Metrics {
+    // registering metrics only fails on duplicate or invalid names, which are constant here
+    #[allow(clippy::expect_used)]
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("maa_tgbot".to_owned()), None)
+            .expect("valid registry prefix");
+
+        // tasks take anything from seconds to a few hours
+        let buckets = exponential_buckets(1.0, 2.0, 15).expect("valid buckets");
+
+        let metrics = Self {
+            polls: IntCounterVec::new(
+                Opts::new("polls_total", "Polls of /get by device"),
+                &["device"],
+            )
+            .expect("valid metric"),
+            tasks_enqueued: IntCounterVec::new(
+                Opts::new("tasks_enqueued_total", "Tasks queued by type"),
+                &["type"],
+            )
+            .expect("valid metric"),
+            tasks_reported: IntCounterVec::new(
+                Opts::new("tasks_reported_total", "Tasks reported by type and status"),
+                &["type", "status"],
+            )
+            .expect("valid metric"),
+            report_latency: HistogramVec::new(
+                HistogramOpts::new(
+                    "task_report_latency_seconds",
+                    "Time from queueing a task to its report, by type",
+                )
+                .buckets(buckets.clone()),
+                &["type"],
+            )
+            .expect("valid metric"),
+            run_time: HistogramVec::new(
+                HistogramOpts::new(
+                    "task_run_seconds",
+                    "Time from handing a task to MAA to its report, by type",
+                )
+                .buckets(buckets),
+                &["type"],
+            )
+            .expect("valid metric"),
+            telegram_errors: IntCounter::new(
+                "telegram_api_errors_total",
+                "Failed requests to the Telegram Bot API",
+            )
+            .expect("valid metric"),
+            queue_length: IntGaugeVec::new(
+                Opts::new(
+                    "queue_length",
+                    "Tasks queued and not reported yet, by device",
+                ),
+                &["device"],
+            )
+            .expect("valid metric"),
+            device_online: IntGaugeVec::new(
+                Opts::new("device_online", "Whether the device polled recently"),
+                &["device"],
+            )
+            .expect("valid metric"),
+            registry,
+            last_polls: Mutex::new(HashMap::new()),
+        };
+
+        for collector in [
+            Box::new(metrics.polls.clone()) as Box<dyn Collector>,
+            Box::new(metrics.tasks_enqueued.clone()),
+            Box::new(metrics.tasks_reported.clone()),
+            Box::new(metrics.report_latency.clone()),
+            Box::new(metrics.run_time.clone()),
+            Box::new(metrics.telegram_errors.clone()),
+            Box::new(metrics.queue_length.clone()),
+            Box::new(metrics.device_online.clone()),
+        ] {
+            metrics.registry.register(collector).expect("unique metric");
+        }
+
+        metrics
+    }
+
+    pub fn observe_poll(&self, device_id: &str) {
+        self.polls.with_label_values(&[device_id]).inc();
+
+        match self.last_polls.lock() {
+            Ok(mut last_polls) => {
+                last_polls.insert(device_id.to_owned(), Utc::now());
+            }
+            Err(e) => tracing::error!("Error recording poll of device {}: {}", device_id, e),
+        }
+    }
+
+    pub fn observe_enqueued(&self, task_types: &[TaskType]) {
+        for task_type in task_types {
+            self.tasks_enqueued
+                .with_label_values(&[&task_type.to_string()])
+                .inc();
+        }
+    }
+
+    pub fn observe_reported(&self, entry: &HistoryEntry) {
+        let task_type = entry.task_type.to_string();
+
+        self.tasks_reported
+            .with_label_values(&[&task_type, &entry.status.to_string()])
+            .inc();
+        self.report_latency
+            .with_label_values(&[&task_type])
+            .observe(seconds(entry.reported_at - entry.enqueued_at));
+        if let Some(fetched_at) = entry.fetched_at {
+            self.run_time
+                .with_label_values(&[&task_type])
+                .observe(seconds(entry.reported_at - fetched_at));
+        }
+    }
+
+    pub fn observe_telegram_error(&self) {
+        self.telegram_errors.inc();
+    }
+
+    /// Update the gauges from `devices` and encode every metric in the text format.
+    pub fn render(&self, devices: &[Device]) -> Result<String, AppError> {
+        // drop devices that are gone since the last scrape
+        self.queue_length.reset();
+        self.device_online.reset();
+
+        let now = Utc::now();
+        let last_polls = self.last_polls.lock()?;
+        for device in devices {
+            let queue_length = device.users.values().map(|u| u.tasks.len()).sum::<usize>();
+            self.queue_length
+                .with_label_values(&[&device.id])
+                .set(i64::try_from(queue_length).unwrap_or(i64::MAX));
+
+            let online = last_polls
+                .get(&device.id)
+                .is_some_and(|last_poll| now - *last_poll < Duration::seconds(ONLINE_TIMEOUT_SECS));
+            self.device_online
+                .with_label_values(&[&device.id])
+                .set(i64::from(online));
+        }
+        drop(last_polls);
+
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+
+        String::from_utf8(buffer).map_err(|e| AppError::MetricsError(e.to_string()))
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn seconds(duration: Duration) -> f64 {
+    duration.num_milliseconds().max(0) as f64 / 1000.0
+}
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new().route("/metrics", get(get_metrics))
+}
+
+// Method: GET
+#[debug_handler]
+async fn get_metrics(app_state: State<Arc<AppState>>) -> Result<impl IntoResponse, AppError> {
+    let devices = app_state.store().list()?;
+
+    let body = app_state.metrics().render(&devices)?;
+
+    Ok(([(CONTENT_TYPE, prometheus::TEXT_FORMAT)], body))
+}
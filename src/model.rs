@@ -1,43 +1,94 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{collections::HashMap, fmt::Display, str::FromStr};
 
 use serde::{Deserialize, Serialize};
 
-use crate::config::DeviceInfo;
+use crate::{config::DeviceInfo, error::AppError};
 
 #[derive(Deserialize, Debug)]
 pub struct TaskStatus {
     pub user: String,
     pub device: String,
     pub task: String,
-    pub status: String,
+    pub status: ReportStatus,
     pub payload: String,
 }
 
-#[derive(Debug, Clone)]
-pub struct Device {
-    pub id: String,
-    pub name: String,
-    pub users: HashMap<String, User>,
+impl TaskStatus {
+    /// A short description of the payload to keep in the task history.
+    pub fn payload_summary(&self, task_type: &TaskType) -> String {
+        const MAX_CHARS: usize = 100;
+
+        match *task_type {
+            TaskType::CaptureImage | TaskType::CaptureImageNow if !self.payload.is_empty() => {
+                format!("image ({} bytes base64)", self.payload.len())
+            }
+            TaskType::CaptureImage
+            | TaskType::CaptureImageNow
+            | TaskType::HeartBeat
+            | TaskType::StopTask
+            | TaskType::LinkStartBase
+            | TaskType::LinkStartWakeUp
+            | TaskType::LinkStartCombat
+            | TaskType::LinkStartRecruiting
+            | TaskType::LinkStartMall
+            | TaskType::LinkStartMission
+            | TaskType::LinkStartAutoRoguelike
+            | TaskType::LinkStartReclamationAlgorithm => {
+                self.payload.chars().take(MAX_CHARS).collect()
+            }
+        }
+    }
 }
 
-impl Device {
-    pub fn new(id: &str) -> Self {
-        Self {
-            id: id.to_owned(),
-            name: id.to_owned(),
-            users: HashMap::new(),
+/// Result of a task as reported by MAA, statuses this bot does not know are kept as they are.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(from = "String", into = "String")]
+pub enum ReportStatus {
+    Success,
+    Failed,
+    Other(String),
+}
+
+impl ReportStatus {
+    pub fn is_success(&self) -> bool {
+        matches!(*self, ReportStatus::Success)
+    }
+}
+
+impl From<String> for ReportStatus {
+    fn from(status: String) -> Self {
+        match status.as_str() {
+            "SUCCESS" => ReportStatus::Success,
+            "FAILED" => ReportStatus::Failed,
+            _ => ReportStatus::Other(status),
         }
     }
+}
+
+impl From<ReportStatus> for String {
+    fn from(status: ReportStatus) -> Self {
+        status.to_string()
+    }
+}
 
-    pub fn new_with_name(id: String, name: String) -> Self {
-        Self {
-            id,
-            name,
-            users: HashMap::new(),
+#[allow(clippy::absolute_paths)]
+impl Display for ReportStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            ReportStatus::Success => write!(f, "SUCCESS"),
+            ReportStatus::Failed => write!(f, "FAILED"),
+            ReportStatus::Other(ref status) => write!(f, "{status}"),
         }
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Device {
+    pub id: String,
+    pub name: String,
+    pub users: HashMap<String, User>,
+}
+
 impl From<Device> for DeviceInfo {
     fn from(device: Device) -> Self {
         Self {
@@ -57,13 +108,13 @@ impl From<DeviceInfo> for Device {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct User {
     pub id: String,
     pub tasks: Vec<Task>,
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub enum TaskType {
     CaptureImage,
     CaptureImageNow,
@@ -84,6 +135,7 @@ pub enum TaskType {
     #[serde(rename = "LinkStart-ReclamationAlgorithm")]
     LinkStartReclamationAlgorithm,
     HeartBeat,
+    StopTask,
 }
 
 #[allow(clippy::absolute_paths)]
@@ -101,6 +153,7 @@ impl Display for TaskType {
             TaskType::LinkStartAutoRoguelike => write!(f, "LinkStart-AutoRoguelike"),
             TaskType::LinkStartReclamationAlgorithm => write!(f, "LinkStart-ReclamationAlgorithm"),
             TaskType::HeartBeat => write!(f, "HeartBeat"),
+            TaskType::StopTask => write!(f, "StopTask"),
         }
     }
 }
@@ -118,15 +171,42 @@ impl TaskType {
             "LinkStart-Mission".to_owned(),
             "LinkStart-AutoRoguelike".to_owned(),
             "LinkStart-ReclamationAlgorithm".to_owned(),
+            "StopTask".to_owned(),
         ]
     }
+
+    /// Queue priority of tasks of this type, see [`Priority`].
+    pub fn priority(&self) -> Priority {
+        match *self {
+            TaskType::CaptureImageNow | TaskType::StopTask => Priority::High,
+            TaskType::CaptureImage
+            | TaskType::LinkStartBase
+            | TaskType::LinkStartWakeUp
+            | TaskType::LinkStartCombat
+            | TaskType::LinkStartRecruiting
+            | TaskType::LinkStartMall
+            | TaskType::LinkStartMission
+            | TaskType::LinkStartAutoRoguelike
+            | TaskType::LinkStartReclamationAlgorithm
+            | TaskType::HeartBeat => Priority::Normal,
+        }
+    }
+}
+
+/// High priority tasks are queued before the normal ones not handed to MAA yet.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    #[default]
+    Normal,
+    High,
 }
 
-impl From<&str> for TaskType {
+impl FromStr for TaskType {
+    type Err = AppError;
 
-    #[allow(clippy::panic)]
-    fn from(s: &str) -> Self {
-        match s {
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
             "CaptureImage" => Self::CaptureImage,
             "CaptureImageNow" => Self::CaptureImageNow,
             "LinkStart-Base" => Self::LinkStartBase,
@@ -138,12 +218,13 @@ impl From<&str> for TaskType {
             "LinkStart-AutoRoguelike" => Self::LinkStartAutoRoguelike,
             "LinkStart-ReclamationAlgorithm" => Self::LinkStartReclamationAlgorithm,
             "HeartBeat" => Self::HeartBeat,
-            _ => panic!("Invalid task type"),
-        }
+            "StopTask" => Self::StopTask,
+            _ => return Err(AppError::InvalidTaskType(s.to_owned())),
+        })
     }
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Task {
     pub id: String,
     #[serde(rename = "type")]
@@ -158,10 +239,6 @@ impl Task {
         }
     }
 
-    pub fn from_str(task_type: &str) -> Self {
-        Self::new(task_type.into())
-    }
-
     pub fn capture_image_task() -> Self {
         Self::new(TaskType::CaptureImage)
     }
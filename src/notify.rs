@@ -0,0 +1,52 @@
+use async_trait::async_trait;
+use chrono::Duration;
+
+use crate::{
+    error::AppError,
+    model::{ReportStatus, TaskType},
+};
+
+mod telegram;
+mod webhook;
+
+pub use telegram::TelegramNotifier;
+pub use webhook::WebhookNotifier;
+
+/// A task reported by MAA, sent to every configured [`Notifier`].
+#[derive(Clone, Debug)]
+pub struct TaskReport {
+    pub task_id: String,
+    pub task_type: TaskType,
+    pub device: String,
+    pub user: String,
+    pub status: ReportStatus,
+    /// Decoded screenshot of `CaptureImage` and `CaptureImageNow` tasks.
+    pub image: Option<Vec<u8>>,
+    /// Task MAA was running when answering a `HeartBeat`, `None` if it was idle.
+    pub running_task: Option<RunningTask>,
+    /// Set for task types retried automatically.
+    pub attempt: Option<Attempt>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Attempt {
+    /// 1-based, counting the first run.
+    pub number: u32,
+    pub max: u32,
+    /// Delay before the next attempt is handed to MAA, `None` if there is none.
+    pub retry_in: Option<Duration>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RunningTask {
+    pub id: String,
+    pub task_type: TaskType,
+}
+
+/// A destination for task reports.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Send `report`, skipping the first `sent` requests, which went out on an earlier attempt,
+    /// and counting in `sent` the ones that succeed.
+    async fn notify(&self, report: &TaskReport, sent: &mut usize) -> Result<(), AppError>;
+}
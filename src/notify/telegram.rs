@@ -0,0 +1,96 @@
+use async_trait::async_trait;
+use teloxide::{
+    adaptors::Throttle,
+    payloads::SendMessageSetters,
+    requests::{Request, Requester},
+    types::{ChatId, InputFile},
+    Bot,
+};
+
+use crate::{bot::retry_markup, error::AppError, model::TaskType};
+
+use super::{Notifier, TaskReport};
+
+/// Sends reports as messages and photos to the bot's user.
+#[allow(clippy::module_name_repetitions)]
+pub struct TelegramNotifier {
+    bot: Throttle<Bot>,
+    chat_id: ChatId,
+}
+
+impl TelegramNotifier {
+    pub fn new(bot: Throttle<Bot>, tg_user_id: i64) -> Self {
+        Self {
+            bot,
+            chat_id: ChatId(tg_user_id),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for TelegramNotifier {
+    async fn notify(&self, report: &TaskReport, sent: &mut usize) -> Result<(), AppError> {
+        let failed = !report.status.is_success();
+
+        let mut lines = vec![];
+        match report.attempt {
+            Some(ref attempt) if failed || attempt.number > 1 => {
+                lines.push(format!(
+                    "Task {} finished. Status: {} (attempt {}/{})",
+                    report.task_type, report.status, attempt.number, attempt.max
+                ));
+                match attempt.retry_in {
+                    Some(delay) => lines.push(format!("Retrying in {}s.", delay.num_seconds())),
+                    None if failed => lines.push("No attempts left.".to_owned()),
+                    None => {}
+                }
+            }
+            Some(_) | None => lines.push(format!(
+                "Task {} finished. Status: {}",
+                report.task_type, report.status
+            )),
+        }
+
+        // the message, the photo and the running task are sent in this order, those sent by an
+        // earlier attempt are skipped
+        let mut part = 0;
+        if *sent <= part {
+            let mut request = self.bot.send_message(self.chat_id, lines.join("\n"));
+            // offer to retry unless it is retried anyway
+            if failed && report.attempt.as_ref().is_none_or(|a| a.retry_in.is_none()) {
+                request = request.reply_markup(retry_markup(&report.task_id));
+            }
+            request.send().await?;
+            *sent += 1;
+        }
+        part += 1;
+
+        if let Some(ref image) = report.image {
+            if *sent <= part {
+                self.bot
+                    .send_photo(self.chat_id, InputFile::memory(image.clone()))
+                    .send()
+                    .await?;
+                *sent += 1;
+            }
+            part += 1;
+        }
+
+        if matches!(report.task_type, TaskType::HeartBeat) && *sent <= part {
+            let msg = report.running_task.as_ref().map_or_else(
+                || "No task is running.".to_owned(),
+                |running| {
+                    format!(
+                        "Task {} is running.\nTask id: {}",
+                        running.task_type, running.id
+                    )
+                },
+            );
+
+            self.bot.send_message(self.chat_id, msg).send().await?;
+            *sent += 1;
+        }
+
+        Ok(())
+    }
+}
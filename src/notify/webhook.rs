@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use serde::Serialize;
+
+use crate::{
+    config::WebhookConfig,
+    error::AppError,
+    model::{ReportStatus, TaskType},
+};
+
+use super::{Notifier, TaskReport};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// POSTs reports as JSON to an HTTP endpoint.
+#[allow(clippy::module_name_repetitions)]
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+    config: WebhookConfig,
+}
+
+#[derive(Serialize)]
+struct WebhookRunningTask<'report> {
+    id: &'report str,
+    #[serde(rename = "type")]
+    task_type: &'report TaskType,
+}
+
+#[derive(Serialize)]
+struct WebhookAttempt {
+    number: u32,
+    max: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_in_secs: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct WebhookBody<'report> {
+    task: &'report str,
+    #[serde(rename = "type")]
+    task_type: &'report TaskType,
+    device: &'report str,
+    user: &'report str,
+    status: &'report ReportStatus,
+    /// Base64 encoded screenshot, only if enabled for the webhook.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    running_task: Option<WebhookRunningTask<'report>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attempt: Option<WebhookAttempt>,
+}
+
+impl WebhookNotifier {
+    pub fn new(config: WebhookConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            config,
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    async fn notify(&self, report: &TaskReport, _sent: &mut usize) -> Result<(), AppError> {
+        let image = report
+            .image
+            .as_ref()
+            .filter(|_| self.config.include_image)
+            .map(|image| BASE64_STANDARD.encode(image));
+
+        let body = WebhookBody {
+            task: &report.task_id,
+            task_type: &report.task_type,
+            device: &report.device,
+            user: &report.user,
+            status: &report.status,
+            image,
+            running_task: report.running_task.as_ref().map(|r| WebhookRunningTask {
+                id: &r.id,
+                task_type: &r.task_type,
+            }),
+            attempt: report.attempt.as_ref().map(|a| WebhookAttempt {
+                number: a.number,
+                max: a.max,
+                retry_in_secs: a.retry_in.map(|d| d.num_seconds()),
+            }),
+        };
+
+        self.client
+            .post(&self.config.url)
+            .timeout(TIMEOUT)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
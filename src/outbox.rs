@@ -0,0 +1,332 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    task::JoinSet,
+    time::sleep,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    error::AppError,
+    notify::{Notifier, TaskReport},
+    state::AppState,
+};
+
+type Worker = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Reports waiting to be sent to the notifiers, so that `/report` never waits for Telegram or
+/// a webhook.
+///
+/// The queue is only delivered while [`run`] is running.
+pub struct Outbox {
+    sender: UnboundedSender<TaskReport>,
+    /// Taken by [`run`], along with the worker of the throttled bot the Telegram notifier
+    /// sends with.
+    receiver: Mutex<Option<(UnboundedReceiver<TaskReport>, Worker)>>,
+    pending: AtomicUsize,
+}
+
+impl Outbox {
+    pub fn new<F>(throttle_worker: F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (sender, receiver) = unbounded_channel();
+
+        Self {
+            sender,
+            receiver: Mutex::new(Some((receiver, Box::pin(throttle_worker)))),
+            pending: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn push(&self, report: TaskReport) -> Result<(), AppError> {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+
+        self.sender.send(report).map_err(|e| {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+            AppError::ServerError(format!(
+                "notification queue closed, dropping report of task {}",
+                e.0.task_id
+            ))
+        })
+    }
+
+    /// Reports queued or being delivered.
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    fn done(&self) {
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// How failed notifications are retried.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_mins(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the next attempt after `attempt` failed with `error`, `None` to give up.
+    ///
+    /// The backoff doubles with every attempt, but is never shorter than what Telegram asks
+    /// for. Only rate limits, network errors and webhook errors are retried.
+    pub fn delay(&self, attempt: u32, error: &AppError) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff);
+
+        match *error {
+            AppError::TelegramRetryAfter(secs) => Some(backoff.max(Duration::from_secs(secs))),
+            AppError::TelegramNetworkError(_) | AppError::WebhookError(_) => Some(backoff),
+            AppError::DeviceNotFound(_)
+            | AppError::UserNotFound(_)
+            | AppError::PoisonError(_)
+            | AppError::TaskNotFound(_)
+            | AppError::TeloxideError(_)
+            | AppError::NoDeviceRegistered
+            | AppError::PersistError(_)
+            | AppError::InvalidTaskType(_)
+            | AppError::InvalidArgument(_)
+            | AppError::ExportError(_)
+            | AppError::Unauthorized
+            | AppError::Forbidden(_)
+            | AppError::MetricsError(_)
+            | AppError::ConfigError(_)
+            | AppError::ServerError(_) => None,
+        }
+    }
+}
+
+/// A report handed to the workers of the notifiers, done once every one of them is.
+struct Delivery {
+    report: TaskReport,
+    remaining: AtomicUsize,
+}
+
+/// The queue of a notifier, delivered in order by its own worker so that a failing notifier
+/// does not hold back the others.
+struct Lane {
+    notifier: Arc<dyn Notifier>,
+    sender: UnboundedSender<Arc<Delivery>>,
+}
+
+/// Deliver the queued reports until `shutdown` is cancelled, then deliver the remaining ones
+/// once each without retrying.
+pub async fn run(app_state: Arc<AppState>, retry: RetryPolicy, shutdown: CancellationToken) {
+    let outbox = app_state.outbox();
+    let taken = match outbox.receiver.lock() {
+        Ok(mut receiver) => receiver.take(),
+        Err(e) => {
+            tracing::error!("Error taking the notification queue: {}", e);
+            return;
+        }
+    };
+    let Some((mut receiver, throttle_worker)) = taken else {
+        tracing::error!("The notification queue is already being delivered");
+        return;
+    };
+
+    tokio::spawn(throttle_worker);
+
+    let mut lanes = vec![];
+    let mut workers = JoinSet::new();
+    loop {
+        tokio::select! {
+            biased;
+            () = shutdown.cancelled() => break,
+            report = receiver.recv() => {
+                let Some(report) = report else { break };
+                dispatch(&app_state, report, &mut lanes, &mut workers, retry, &shutdown);
+            }
+        }
+    }
+
+    receiver.close();
+    if outbox.pending() > 0 {
+        tracing::info!("Delivering {} pending notifications", outbox.pending());
+    }
+    while let Ok(report) = receiver.try_recv() {
+        dispatch(
+            &app_state,
+            report,
+            &mut lanes,
+            &mut workers,
+            retry,
+            &shutdown,
+        );
+    }
+
+    // the workers stop once their queue is empty
+    lanes.clear();
+    while let Some(result) = workers.join_next().await {
+        if let Err(e) = result {
+            tracing::error!("Notification worker failed: {}", e);
+        }
+    }
+}
+
+/// Hand a report to the worker of every notifier, starting workers for new notifiers and
+/// stopping those of the notifiers removed by a reload.
+fn dispatch(
+    app_state: &Arc<AppState>,
+    report: TaskReport,
+    lanes: &mut Vec<Lane>,
+    workers: &mut JoinSet<()>,
+    retry: RetryPolicy,
+    shutdown: &CancellationToken,
+) {
+    let notifiers = match app_state.notifiers() {
+        Ok(notifiers) => notifiers,
+        Err(e) => {
+            tracing::error!("Error notifying report of task {}: {}", report.task_id, e);
+            app_state.outbox().done();
+            return;
+        }
+    };
+
+    lanes.retain(|lane| notifiers.iter().any(|n| Arc::ptr_eq(n, &lane.notifier)));
+
+    let delivery = Arc::new(Delivery {
+        report,
+        remaining: AtomicUsize::new(notifiers.len()),
+    });
+    if notifiers.is_empty() {
+        app_state.outbox().done();
+    }
+
+    for notifier in notifiers.iter() {
+        if !lanes.iter().any(|l| Arc::ptr_eq(&l.notifier, notifier)) {
+            let (sender, receiver) = unbounded_channel();
+            workers.spawn(work(
+                Arc::clone(app_state),
+                Arc::clone(notifier),
+                receiver,
+                retry,
+                shutdown.clone(),
+            ));
+            lanes.push(Lane {
+                notifier: Arc::clone(notifier),
+                sender,
+            });
+        }
+        let Some(lane) = lanes.iter().find(|l| Arc::ptr_eq(&l.notifier, notifier)) else {
+            continue;
+        };
+
+        if let Err(e) = lane.sender.send(Arc::clone(&delivery)) {
+            tracing::error!(
+                "Notification worker stopped, dropping report of task {}",
+                e.0.report.task_id
+            );
+            finish(app_state, &delivery);
+        }
+    }
+}
+
+/// Deliver the reports handed to a notifier in order, once each without retrying after the
+/// shutdown.
+async fn work(
+    app_state: Arc<AppState>,
+    notifier: Arc<dyn Notifier>,
+    mut receiver: UnboundedReceiver<Arc<Delivery>>,
+    retry: RetryPolicy,
+    shutdown: CancellationToken,
+) {
+    while let Some(delivery) = receiver.recv().await {
+        let retry = if shutdown.is_cancelled() {
+            RetryPolicy {
+                max_attempts: 1,
+                ..retry
+            }
+        } else {
+            retry
+        };
+
+        deliver(
+            &app_state,
+            notifier.as_ref(),
+            &delivery.report,
+            retry,
+            &shutdown,
+        )
+        .await;
+        finish(&app_state, &delivery);
+    }
+}
+
+fn finish(app_state: &AppState, delivery: &Delivery) {
+    if delivery.remaining.fetch_sub(1, Ordering::SeqCst) == 1 {
+        app_state.outbox().done();
+    }
+}
+
+/// Send a report to a notifier, retrying as `retry` says.
+async fn deliver(
+    app_state: &AppState,
+    notifier: &dyn Notifier,
+    report: &TaskReport,
+    retry: RetryPolicy,
+    shutdown: &CancellationToken,
+) {
+    let mut attempt = 1;
+    let mut sent = 0;
+    while let Err(e) = notifier.notify(report, &mut sent).await {
+        if e.is_telegram_error() {
+            app_state.metrics().observe_telegram_error();
+        }
+
+        let Some(delay) = retry.delay(attempt, &e) else {
+            tracing::error!(
+                "Error notifying report of task {} after {} attempts: {}",
+                report.task_id,
+                attempt,
+                e
+            );
+            break;
+        };
+        tracing::warn!(
+            "Error notifying report of task {}, retrying in {}s: {}",
+            report.task_id,
+            delay.as_secs_f64(),
+            e
+        );
+
+        tokio::select! {
+            () = sleep(delay) => {}
+            () = shutdown.cancelled() => {
+                tracing::error!("Dropping notification of task {} on shutdown: {}", report.task_id, e);
+                break;
+            }
+        }
+        attempt += 1;
+    }
+}
@@ -0,0 +1,50 @@
+use std::sync::Arc;
+
+use teloxide::{requests::Requester, types::ChatId};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::{error::AppError, state::AppState};
+
+/// Reload the config file and tell the Telegram user what changed or why the reload was
+/// rejected, in which case the previous config stays in effect.
+pub async fn reload_and_report(app_state: &AppState) -> Result<(), AppError> {
+    let msg = match app_state.reload_config() {
+        Ok(changes) if changes.is_empty() => "Config reloaded, nothing changed.".to_owned(),
+        Ok(changes) => {
+            tracing::info!("Config reloaded: {}", changes.join("; "));
+            format!("Config reloaded:\n- {}", changes.join("\n- "))
+        }
+        Err(e) => {
+            tracing::error!("Config reload rejected: {}", e);
+            format!("Config reload rejected, keeping the current config.\n{e}")
+        }
+    };
+
+    // sent after applying, so a changed authorized user gets the message
+    app_state
+        .bot
+        .send_message(ChatId(app_state.tg_user_id()?), msg)
+        .await?;
+
+    Ok(())
+}
+
+/// Reload the config on every SIGHUP.
+#[cfg(unix)]
+pub async fn watch_sighup(app_state: Arc<AppState>) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            tracing::error!("Error listening for SIGHUP: {}", e);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        tracing::info!("SIGHUP received, reloading config");
+        if let Err(e) = reload_and_report(&app_state).await {
+            tracing::error!("Error reporting config reload: {}", e);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use std::future::pending;
+
+use tokio::signal::ctrl_c;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Wait for SIGINT or, on unix, SIGTERM.
+pub async fn signal_received() {
+    #[cfg(unix)]
+    {
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(e) => {
+                tracing::error!("Error listening for SIGTERM: {}", e);
+                wait_for_ctrl_c().await;
+                return;
+            }
+        };
+
+        tokio::select! {
+            () = wait_for_ctrl_c() => {}
+            _ = terminate.recv() => tracing::info!("SIGTERM received"),
+        }
+    }
+
+    #[cfg(not(unix))]
+    wait_for_ctrl_c().await;
+}
+
+async fn wait_for_ctrl_c() {
+    match ctrl_c().await {
+        Ok(()) => tracing::info!("SIGINT received"),
+        Err(e) => {
+            tracing::error!("Error listening for SIGINT: {}", e);
+            // never resolve rather than shutting down right away
+            pending::<()>().await;
+        }
+    }
+}
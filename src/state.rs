@@ -0,0 +1,480 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use teloxide::{
+    adaptors::{throttle::Settings as ThrottleSettings, Throttle},
+    Bot,
+};
+
+use crate::{
+    config::{Config, DeviceInfo, TaskRetryConfig, DEFAULT_DIALOG_TIMEOUT_SECS},
+    error::AppError,
+    metrics::Metrics,
+    model::{ReportStatus, Task, TaskType, User},
+    notify::{Attempt, Notifier, TaskReport, TelegramNotifier, WebhookNotifier},
+    outbox::Outbox,
+    store::{Dependency, HistoryEntry, Reported, TaskRecord, TaskStore},
+};
+
+/// Shared state of the bot and the MAA endpoints, injected into both the axum and the
+/// teloxide handlers.
+///
+/// All the methods are synchronous and do not hold any lock after returning, so they are
+/// safe to call from async handlers.
+pub struct AppState {
+    store: Arc<dyn TaskStore>,
+    metrics: Metrics,
+    pub bot: Bot,
+    /// Rate limited `bot` for notifications, which may come in bursts.
+    throttled_bot: Throttle<Bot>,
+    outbox: Outbox,
+    /// File the config is reloaded from, reloading is disabled if not set.
+    config_file: Option<String>,
+    settings: RwLock<Settings>,
+    /// Last time the Telegram Bot API answered `get_me`.
+    telegram_checked_at: Mutex<Option<DateTime<Utc>>>,
+}
+
+/// The part of the config applied without a restart.
+struct Settings {
+    config: Config,
+    tg_user_id: i64,
+    notifiers: Arc<Vec<Arc<dyn Notifier>>>,
+    allowed_devices: Option<HashMap<String, String>>,
+    replay_window: Option<Duration>,
+    admin_token: Option<String>,
+    task_retries: Vec<TaskRetryConfig>,
+    dialog_timeout: Duration,
+}
+
+impl Settings {
+    fn new(config: &Config, bot: &Throttle<Bot>) -> Self {
+        let allowed_devices = config.devices.as_ref().map(|devices| {
+            devices
+                .iter()
+                .map(|device| (device.id.clone(), device.name.clone()))
+                .collect()
+        });
+
+        let mut notifiers: Vec<Arc<dyn Notifier>> = vec![Arc::new(TelegramNotifier::new(
+            bot.clone(),
+            config.telegram_user_id,
+        ))];
+        for webhook in config.webhooks.iter().flatten() {
+            notifiers.push(Arc::new(WebhookNotifier::new(webhook.clone())));
+        }
+
+        Self {
+            config: config.clone(),
+            tg_user_id: config.telegram_user_id,
+            notifiers: Arc::new(notifiers),
+            allowed_devices,
+            // too long a window to represent replays forever
+            replay_window: config.task_replay_window_secs.map(|secs| {
+                i64::try_from(secs)
+                    .ok()
+                    .and_then(Duration::try_seconds)
+                    .unwrap_or_else(Duration::max_value)
+            }),
+            admin_token: config.admin_token.clone().filter(|t| !t.is_empty()),
+            task_retries: config.task_retries.clone().unwrap_or_default(),
+            dialog_timeout: i64::try_from(
+                config
+                    .dialog_timeout_secs
+                    .unwrap_or(DEFAULT_DIALOG_TIMEOUT_SECS),
+            )
+            .ok()
+            .and_then(Duration::try_seconds)
+            .unwrap_or_else(Duration::max_value),
+        }
+    }
+}
+
+impl AppState {
+    pub fn new(config: &Config, bot: Bot, store: Arc<dyn TaskStore>) -> Self {
+        // retrying is left to the outbox, which gives up at some point
+        let (throttled_bot, throttle_worker) =
+            Throttle::with_settings(bot.clone(), ThrottleSettings::default().no_retry());
+
+        Self {
+            store,
+            metrics: Metrics::new(),
+            settings: RwLock::new(Settings::new(config, &throttled_bot)),
+            bot,
+            throttled_bot,
+            outbox: Outbox::new(throttle_worker),
+            config_file: None,
+            telegram_checked_at: Mutex::new(None),
+        }
+    }
+
+    /// Allow reloading the config from `file`.
+    pub fn with_config_file(mut self, file: &str) -> Self {
+        self.config_file = Some(file.to_owned());
+        self
+    }
+
+    pub fn store(&self) -> &dyn TaskStore {
+        self.store.as_ref()
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// The Telegram user allowed to use the bot.
+    pub fn tg_user_id(&self) -> Result<i64, AppError> {
+        Ok(self.settings.read()?.tg_user_id)
+    }
+
+    /// How long a bot dialog waits for the next button press.
+    pub fn dialog_timeout(&self) -> Result<Duration, AppError> {
+        Ok(self.settings.read()?.dialog_timeout)
+    }
+
+    /// Read the config file again and apply it, returning a description of every change.
+    pub fn reload_config(&self) -> Result<Vec<String>, AppError> {
+        let file = self
+            .config_file
+            .as_deref()
+            .ok_or(AppError::ConfigError("no config file to reload".to_owned()))?;
+
+        self.apply_config(&Config::load(file)?)
+    }
+
+    /// Apply the device allow-list and names, the authorized user, the replay window, the
+    /// admin token, the webhooks and the task retries of `config`.
+    ///
+    /// Other settings only take effect on restart, they are listed in the returned changes.
+    pub fn apply_config(&self, config: &Config) -> Result<Vec<String>, AppError> {
+        let settings = Settings::new(config, &self.throttled_bot);
+
+        for device in config.devices.iter().flatten() {
+            if self.store.rename(&device.id, &device.name)? {
+                tracing::info!("Renamed device {} to {}", device.id, device.name);
+            }
+        }
+
+        let mut current = self.settings.write()?;
+        let changes = current.config.changes(config);
+        *current = settings;
+
+        Ok(changes)
+    }
+
+    /// Whether `token` grants access to the admin API, which is disabled without a token
+    /// configured.
+    pub fn is_admin_token(&self, token: &str) -> Result<bool, AppError> {
+        let settings = self.settings.read()?;
+
+        Ok(settings.admin_token.as_deref().is_some_and(|expected| {
+            // compare in constant time so response times do not reveal the token
+            expected.len() == token.len()
+                && expected
+                    .bytes()
+                    .zip(token.bytes())
+                    .fold(0, |acc, (a, b)| acc | (a ^ b))
+                    == 0
+        }))
+    }
+
+    pub fn telegram_checked_at(&self) -> Option<DateTime<Utc>> {
+        self.telegram_checked_at
+            .lock()
+            .map_or(None, |checked_at| *checked_at)
+    }
+
+    pub fn set_telegram_checked_at(&self, time: DateTime<Utc>) {
+        if let Ok(mut checked_at) = self.telegram_checked_at.lock() {
+            *checked_at = Some(time);
+        }
+    }
+
+    pub fn outbox(&self) -> &Outbox {
+        &self.outbox
+    }
+
+    /// Queue a report to be sent to every notifier by [`crate::outbox::run`].
+    pub fn notify(&self, report: TaskReport) -> Result<(), AppError> {
+        self.outbox.push(report)
+    }
+
+    /// The notifiers of the current config.
+    ///
+    /// They are kept by the caller even if the config is reloaded meanwhile.
+    pub fn notifiers(&self) -> Result<Arc<Vec<Arc<dyn Notifier>>>, AppError> {
+        Ok(Arc::clone(&self.settings.read()?.notifiers))
+    }
+
+    pub fn task_type(&self, task_id: &str) -> Result<TaskType, AppError> {
+        Ok(self.store.get(task_id)?.task.task_type)
+    }
+
+    pub fn users(&self, device_id: &str) -> Result<Vec<String>, AppError> {
+        self.store
+            .list()?
+            .into_iter()
+            .find(|d| d.id == device_id)
+            .map(|d| d.users.into_keys().collect())
+            .ok_or(AppError::DeviceNotFound(device_id.to_owned()))
+    }
+
+    pub fn devices(&self) -> Result<Vec<DeviceInfo>, AppError> {
+        let devices = self.store.list()?.into_iter().map(Into::into).collect();
+
+        Ok(devices)
+    }
+
+    pub fn is_single_user(&self) -> Result<bool, AppError> {
+        let devices = self.store.list()?;
+
+        Ok(devices.len() == 1 && devices.iter().all(|d| d.users.len() == 1))
+    }
+
+    pub fn single_device_and_user(&self) -> Result<(DeviceInfo, User), AppError> {
+        let device = self
+            .store
+            .list()?
+            .into_iter()
+            .next()
+            .ok_or(AppError::NoDeviceRegistered)?;
+
+        let user = device
+            .users
+            .values()
+            .next()
+            .ok_or(AppError::NoDeviceRegistered)?
+            .clone();
+
+        Ok((device.into(), user))
+    }
+
+    /// Append a task to a single user of a device, returning the queued tasks.
+    pub fn append_task(
+        &self,
+        device_id: &str,
+        user_id: &str,
+        task_type: &TaskType,
+    ) -> Result<Vec<Task>, AppError> {
+        let mut records = vec![];
+        push_task(&mut records, device_id, user_id, task_type);
+        let tasks = records.iter().map(|r| r.task.clone()).collect();
+
+        self.enqueue(records)?;
+
+        Ok(tasks)
+    }
+
+    /// Same as [`Self::append_task`], the task being held back until `dependency` is met.
+    pub fn append_dependent_task(
+        &self,
+        device_id: &str,
+        user_id: &str,
+        task_type: &TaskType,
+        dependency: &Dependency,
+    ) -> Result<Vec<Task>, AppError> {
+        let mut records = vec![];
+        push_task(&mut records, device_id, user_id, task_type);
+        // the screenshot after the task is released or dropped along with it
+        for record in &mut records {
+            record.depends_on = Some(dependency.clone());
+        }
+        let tasks = records.iter().map(|r| r.task.clone()).collect();
+
+        self.enqueue(records)?;
+
+        Ok(tasks)
+    }
+
+    /// Queue a reported task again on demand, its attempts counted from the first one again.
+    ///
+    /// Returns the queued tasks, `None` if the task was retried already.
+    pub fn retry_task(&self, entry: &HistoryEntry) -> Result<Option<Vec<Task>>, AppError> {
+        let mut records = vec![];
+        push_task(&mut records, &entry.device, &entry.user, &entry.task_type);
+        let tasks = records.iter().map(|r| r.task.clone()).collect();
+        let task_types: Vec<_> = records.iter().map(|r| r.task.task_type.clone()).collect();
+
+        if !self.store.retry(&entry.task_id, records)? {
+            return Ok(None);
+        }
+        self.metrics.observe_enqueued(&task_types);
+
+        Ok(Some(tasks))
+    }
+
+    /// Move a reported task to the history, queueing its next attempt in its place if its type
+    /// is retried and it did not succeed, returning the attempt to notify.
+    ///
+    /// The attempt is `None` for task types without retries configured.
+    pub fn mark_reported(
+        &self,
+        record: &TaskRecord,
+        status: &ReportStatus,
+        payload_summary: &str,
+    ) -> Result<(Reported, Option<Attempt>), AppError> {
+        let Some(policy) = self
+            .settings
+            .read()?
+            .task_retries
+            .iter()
+            .find(|r| r.task_type == record.task.task_type)
+            .cloned()
+        else {
+            let reported = self
+                .store
+                .mark_reported(&record.task.id, status, payload_summary, vec![])?;
+            return Ok((reported, None));
+        };
+
+        let mut retry = vec![];
+        let mut retry_in = None;
+        if !status.is_success() && record.attempt < policy.max_attempts {
+            let delay = policy.backoff(record.attempt);
+            retry = next_attempt(
+                &record.device,
+                &record.user,
+                &record.task.task_type,
+                record.attempt,
+                delay,
+            );
+            retry_in = Some(delay);
+        }
+        let task_types: Vec<_> = retry.iter().map(|r| r.task.task_type.clone()).collect();
+
+        let reported = self
+            .store
+            .mark_reported(&record.task.id, status, payload_summary, retry)?;
+        if let Some(delay) = retry_in.filter(|_| matches!(reported, Reported::First(_))) {
+            self.metrics.observe_enqueued(&task_types);
+            tracing::info!(
+                "Retrying task {} in {}s, attempt {}/{}",
+                record.task.id,
+                delay.num_seconds(),
+                record.attempt + 1,
+                policy.max_attempts
+            );
+        }
+
+        Ok((
+            reported,
+            Some(Attempt {
+                number: record.attempt,
+                max: policy.max_attempts,
+                retry_in,
+            }),
+        ))
+    }
+
+    /// Append a task to every user of every known device as a single batch.
+    ///
+    /// Returns the number of users the task was appended to.
+    pub fn append_task_to_all(&self, task_type: &TaskType) -> Result<usize, AppError> {
+        let mut records = vec![];
+        let mut count = 0;
+
+        for device in self.store.list()? {
+            for user_id in device.users.keys() {
+                push_task(&mut records, &device.id, user_id, task_type);
+                count += 1;
+            }
+        }
+
+        self.enqueue(records)?;
+
+        Ok(count)
+    }
+
+    /// Register the polling device and user if needed and return the tasks to hand to them.
+    ///
+    /// Returns an empty list for devices not in the allow-list. See [`TaskStore`] for which
+    /// tasks are returned.
+    pub fn poll(&self, device_id: &str, user_id: &str) -> Result<Vec<Task>, AppError> {
+        let settings = self.settings.read()?;
+        let device = if let Some(ref allowed_devices) = settings.allowed_devices {
+            let Some(device_name) = allowed_devices.get(device_id) else {
+                return Ok(vec![]);
+            };
+            DeviceInfo {
+                id: device_id.to_owned(),
+                name: device_name.clone(),
+            }
+        } else {
+            DeviceInfo {
+                id: device_id.to_owned(),
+                name: device_id.to_owned(),
+            }
+        };
+        let replay_window = settings.replay_window;
+        drop(settings);
+
+        self.metrics.observe_poll(device_id);
+
+        if self.store.register(&device, user_id)? {
+            tracing::info!("New user {} on device {} ({})", user_id, device.id, device.name);
+        }
+
+        self.store
+            .fetch_for_device(device_id, user_id, replay_window)
+    }
+
+    fn enqueue(&self, records: Vec<TaskRecord>) -> Result<(), AppError> {
+        let task_types: Vec<_> = records.iter().map(|r| r.task.task_type.clone()).collect();
+
+        self.store.enqueue(records)?;
+        self.metrics.observe_enqueued(&task_types);
+
+        Ok(())
+    }
+}
+
+/// The attempt after `attempt` of a task with its screenshot, held back from MAA for `delay`.
+fn next_attempt(
+    device_id: &str,
+    user_id: &str,
+    task_type: &TaskType,
+    attempt: u32,
+    delay: Duration,
+) -> Vec<TaskRecord> {
+    let not_before = (delay > Duration::zero()).then(|| {
+        Utc::now()
+            .checked_add_signed(delay)
+            .unwrap_or(DateTime::<Utc>::MAX_UTC)
+    });
+
+    let mut records = vec![];
+    push_task(&mut records, device_id, user_id, task_type);
+    for record in &mut records {
+        record.not_before = not_before;
+    }
+    if let Some(record) = records.first_mut() {
+        record.attempt = attempt + 1;
+    }
+
+    records
+}
+
+/// Push a task for the user, followed by an extra `CaptureImage` task if the task itself is not one.
+///
+/// Both get the priority of the task, so that the screenshot stays right after it.
+fn push_task(records: &mut Vec<TaskRecord>, device_id: &str, user_id: &str, task_type: &TaskType) {
+    let priority = task_type.priority();
+    let task = Task::new(task_type.clone());
+    let task_id = task.id.clone();
+
+    records.push(TaskRecord {
+        priority,
+        ..TaskRecord::new(task, device_id, user_id)
+    });
+
+    if !matches!(*task_type, TaskType::CaptureImage) {
+        records.push(TaskRecord {
+            priority,
+            capture_of: Some(task_id),
+            ..TaskRecord::new(Task::capture_image_task(), device_id, user_id)
+        });
+    }
+}
@@ -0,0 +1,267 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::DeviceInfo,
+    error::AppError,
+    model::{Device, Priority, ReportStatus, Task, TaskType},
+};
+
+mod file;
+mod memory;
+
+pub use file::FileStore;
+pub use memory::MemoryStore;
+
+/// A task together with the device and user it was queued for.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TaskRecord {
+    pub task: Task,
+    pub device: String,
+    pub user: String,
+    #[serde(default = "Utc::now")]
+    pub enqueued_at: DateTime<Utc>,
+    /// When the task was first handed to MAA.
+    pub fetched_at: Option<DateTime<Utc>>,
+    /// 1 for the first run of a task, incremented every time it is retried.
+    #[serde(default = "first_attempt")]
+    pub attempt: u32,
+    /// The task is held back from MAA until then.
+    pub not_before: Option<DateTime<Utc>>,
+    /// The task is held back from MAA until this is met, and dropped if it cannot be.
+    pub depends_on: Option<Dependency>,
+    #[serde(default)]
+    pub priority: Priority,
+    /// The task this screenshot is taken after, cancelled along with it.
+    pub capture_of: Option<String>,
+}
+
+/// A condition on the report of another task.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Dependency {
+    #[serde(rename = "task")]
+    pub task_id: String,
+    #[serde(default)]
+    pub condition: Condition,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Condition {
+    #[default]
+    Success,
+    Failure,
+    /// Any report, but not a cancellation.
+    Always,
+}
+
+impl Condition {
+    pub fn is_met(self, status: &ReportStatus) -> bool {
+        match self {
+            Condition::Success => status.is_success(),
+            Condition::Failure => !status.is_success(),
+            Condition::Always => true,
+        }
+    }
+}
+
+fn first_attempt() -> u32 {
+    1
+}
+
+impl TaskRecord {
+    pub fn new(task: Task, device: &str, user: &str) -> Self {
+        Self {
+            task,
+            device: device.to_owned(),
+            user: user.to_owned(),
+            enqueued_at: Utc::now(),
+            fetched_at: None,
+            attempt: first_attempt(),
+            not_before: None,
+            depends_on: None,
+            priority: Priority::default(),
+            capture_of: None,
+        }
+    }
+
+    /// Whether the task may be handed to MAA at `now`.
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        self.depends_on.is_none() && self.not_before.is_none_or(|not_before| not_before <= now)
+    }
+}
+
+/// A reported task, kept in the bounded task history.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HistoryEntry {
+    pub task_id: String,
+    pub task_type: TaskType,
+    pub device: String,
+    pub user: String,
+    pub enqueued_at: DateTime<Utc>,
+    pub fetched_at: Option<DateTime<Utc>>,
+    pub reported_at: DateTime<Utc>,
+    pub status: ReportStatus,
+    pub payload_summary: String,
+    #[serde(default = "first_attempt")]
+    pub attempt: u32,
+    /// The next attempt queued after this one, automatically or on demand.
+    #[serde(default)]
+    pub retried_as: Option<String>,
+}
+
+impl HistoryEntry {
+    pub fn new(record: TaskRecord, status: &ReportStatus, payload_summary: &str) -> Self {
+        Self {
+            task_id: record.task.id,
+            task_type: record.task.task_type,
+            device: record.device,
+            user: record.user,
+            enqueued_at: record.enqueued_at,
+            fetched_at: record.fetched_at,
+            reported_at: Utc::now(),
+            status: status.clone(),
+            payload_summary: payload_summary.to_owned(),
+            attempt: record.attempt,
+            retried_as: None,
+        }
+    }
+}
+
+/// A change of position in a user's queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Move {
+    Up,
+    Down,
+    Front,
+}
+
+/// Outcome of [`TaskStore::mark_reported`].
+#[derive(Clone, Debug)]
+pub enum Reported {
+    /// The first report of the task, now in the history.
+    First(HistoryEntry),
+    /// The task was reported before, the history entry of that report is left untouched.
+    Again(HistoryEntry),
+}
+
+/// Criteria for [`TaskStore::history`], unset fields match everything.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct HistoryFilter {
+    pub device: Option<String>,
+    #[serde(rename = "type")]
+    pub task_type: Option<TaskType>,
+    /// Earliest report time, inclusive.
+    pub from: Option<DateTime<Utc>>,
+    /// Latest report time, exclusive.
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl HistoryFilter {
+    pub fn matches(&self, entry: &HistoryEntry) -> bool {
+        self.device.as_ref().is_none_or(|d| *d == entry.device)
+            && self
+                .task_type
+                .as_ref()
+                .is_none_or(|t| *t == entry.task_type)
+            && self.from.is_none_or(|from| entry.reported_at >= from)
+            && self.to.is_none_or(|to| entry.reported_at < to)
+    }
+}
+
+/// Storage backend for devices, users and their task queues.
+///
+/// # Delivery semantics
+///
+/// A queued task is handed to MAA by the first [`TaskStore::fetch_for_device`] call after it
+/// was enqueued. After that it is only handed out again while it is unreported and within the
+/// replay window given to `fetch_for_device`, which covers MAA missing a response. Without a
+/// replay window, delivery is at most once. Once reported, a task leaves its queue and is never
+/// handed out again, so an MAA restart does not replay old tasks. A task held back until a later
+/// time, like a retry waiting for its backoff, is not handed out before then.
+///
+/// # Dependencies
+///
+/// A task depending on another one is held back until that one is reported. It is released if
+/// the report meets its condition, and dropped along with its own dependents otherwise or if the
+/// task it depends on is cancelled.
+///
+/// Implementations must be safe to call from async handlers, i.e. no method may block
+/// on anything other than short-lived locks or local I/O.
+#[allow(clippy::module_name_repetitions)]
+pub trait TaskStore: Send + Sync {
+    /// Register a device and one of its users, keeping existing entries untouched.
+    ///
+    /// Returns whether anything new was added.
+    fn register(&self, device: &DeviceInfo, user_id: &str) -> Result<bool, AppError>;
+
+    /// Rename a registered device.
+    ///
+    /// Returns whether the device is known and its name changed.
+    fn rename(&self, device_id: &str, name: &str) -> Result<bool, AppError>;
+
+    /// Queue a batch of tasks atomically: either all records are queued or none.
+    ///
+    /// High priority tasks go before the normal priority ones not handed to MAA yet, others
+    /// are appended.
+    ///
+    /// A dependency must be queued, in the store or earlier in the batch, or reported meeting
+    /// the condition, in which case it is dropped. A dependency on a retried task waits for its
+    /// latest attempt.
+    fn enqueue(&self, records: Vec<TaskRecord>) -> Result<(), AppError>;
+
+    /// Tasks to be handed to MAA for the given device and user, marking them as delivered.
+    fn fetch_for_device(
+        &self,
+        device_id: &str,
+        user_id: &str,
+        replay_window: Option<Duration>,
+    ) -> Result<Vec<Task>, AppError>;
+
+    /// Remove a reported task from its queue and move it to the history.
+    ///
+    /// `retry`, the next attempt of the task with its screenshot if any, is queued as
+    /// [`Self::enqueue`] does, behind the tasks already queued unless it is urgent. The tasks
+    /// depending on the reported task then wait for the next attempt instead.
+    ///
+    /// A task already in the history is reported [`Reported::Again`] instead, so that a report
+    /// retried by MAA is only handled once.
+    fn mark_reported(
+        &self,
+        task_id: &str,
+        status: &ReportStatus,
+        payload_summary: &str,
+        retry: Vec<TaskRecord>,
+    ) -> Result<Reported, AppError>;
+
+    /// Queue `retry`, the next attempt of a reported task, unless the task was retried already.
+    ///
+    /// Returns whether `retry` was queued.
+    fn retry(&self, task_id: &str, retry: Vec<TaskRecord>) -> Result<bool, AppError>;
+
+    /// A task that has not been reported yet.
+    fn get(&self, task_id: &str) -> Result<TaskRecord, AppError>;
+
+    /// A reported task still in the history.
+    fn reported(&self, task_id: &str) -> Result<Option<HistoryEntry>, AppError>;
+
+    /// Reported tasks matching `filter`, most recent first.
+    fn history(&self, filter: &HistoryFilter) -> Result<Vec<HistoryEntry>, AppError>;
+
+    /// All known devices with their users and queues.
+    fn list(&self) -> Result<Vec<Device>, AppError>;
+
+    /// Move a queued task within its user's queue, which is the order MAA gets tasks in.
+    ///
+    /// Only tasks not handed to MAA yet can be moved, and only among themselves. A task moves
+    /// along with its screenshot, moving the screenshot moves the task it is taken after.
+    fn move_task(&self, task_id: &str, to: Move) -> Result<(), AppError>;
+
+    /// Remove a task from its queue, dropping its screenshot and the tasks depending on it.
+    fn cancel(&self, task_id: &str) -> Result<TaskRecord, AppError>;
+
+    /// Make sure every change is persisted, called before exiting.
+    fn flush(&self) -> Result<(), AppError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    fs::{read_to_string, rename, write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use chrono::Duration;
+
+use crate::{
+    config::DeviceInfo,
+    error::AppError,
+    model::{Device, ReportStatus, Task},
+};
+
+use super::{
+    memory::MemoryStore, HistoryEntry, HistoryFilter, Move, Reported, TaskRecord, TaskStore,
+};
+
+/// Keeps the state in memory and writes a JSON snapshot to disk after every change.
+///
+/// A change is not undone when its snapshot cannot be written, only [`TaskStore::flush`]
+/// reports write errors.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct FileStore {
+    memory: MemoryStore,
+    path: PathBuf,
+    // serializes writers so that an older snapshot never overwrites a newer one
+    write_lock: Mutex<()>,
+    /// The last snapshot failed to be written.
+    dirty: AtomicBool,
+}
+
+impl FileStore {
+    /// Open the store at `path`, loading the previous snapshot if the file exists.
+    pub fn open(path: &str, history_limit: usize) -> Result<Self, AppError> {
+        let path = PathBuf::from(path);
+
+        let memory = if path.exists() {
+            let content = read_to_string(&path)?;
+            MemoryStore::from_data(serde_json::from_str(&content)?, history_limit)
+        } else {
+            MemoryStore::new(history_limit)
+        };
+
+        Ok(Self {
+            memory,
+            path,
+            write_lock: Mutex::new(()),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    fn save(&self) -> Result<(), AppError> {
+        let _guard = self.write_lock.lock()?;
+
+        let content = serde_json::to_string(&self.memory.snapshot()?)?;
+
+        // write to a temporary file first so a crash never leaves a truncated snapshot
+        let tmp_path = self.path.with_extension("tmp");
+        write(&tmp_path, content)?;
+        rename(&tmp_path, &self.path)?;
+        self.dirty.store(false, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Write a snapshot after a change, which is kept in memory even if writing fails: the
+    /// error is only logged and the snapshot written again on the next poll or change.
+    fn persist(&self) {
+        if let Err(e) = self.save() {
+            tracing::error!("Error persisting state to {}: {}", self.path.display(), e);
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl TaskStore for FileStore {
+    fn register(&self, device: &DeviceInfo, user_id: &str) -> Result<bool, AppError> {
+        let added = self.memory.register(device, user_id)?;
+        if added {
+            self.persist();
+        }
+        Ok(added)
+    }
+
+    fn rename(&self, device_id: &str, name: &str) -> Result<bool, AppError> {
+        let renamed = self.memory.rename(device_id, name)?;
+        if renamed {
+            self.persist();
+        }
+        Ok(renamed)
+    }
+
+    fn enqueue(&self, records: Vec<TaskRecord>) -> Result<(), AppError> {
+        self.memory.enqueue(records)?;
+        self.persist();
+        Ok(())
+    }
+
+    fn fetch_for_device(
+        &self,
+        device_id: &str,
+        user_id: &str,
+        replay_window: Option<Duration>,
+    ) -> Result<Vec<Task>, AppError> {
+        let (tasks, changed) = self.memory.fetch(device_id, user_id, replay_window)?;
+        if changed || self.dirty.load(Ordering::Relaxed) {
+            self.persist();
+        }
+        Ok(tasks)
+    }
+
+    fn mark_reported(
+        &self,
+        task_id: &str,
+        status: &ReportStatus,
+        payload_summary: &str,
+        retry: Vec<TaskRecord>,
+    ) -> Result<Reported, AppError> {
+        let reported = self
+            .memory
+            .mark_reported(task_id, status, payload_summary, retry)?;
+        if matches!(reported, Reported::First(_)) {
+            self.persist();
+        }
+        Ok(reported)
+    }
+
+    fn retry(&self, task_id: &str, retry: Vec<TaskRecord>) -> Result<bool, AppError> {
+        let retried = self.memory.retry(task_id, retry)?;
+        if retried {
+            self.persist();
+        }
+        Ok(retried)
+    }
+
+    fn get(&self, task_id: &str) -> Result<TaskRecord, AppError> {
+        self.memory.get(task_id)
+    }
+
+    fn reported(&self, task_id: &str) -> Result<Option<HistoryEntry>, AppError> {
+        self.memory.reported(task_id)
+    }
+
+    fn history(&self, filter: &HistoryFilter) -> Result<Vec<HistoryEntry>, AppError> {
+        self.memory.history(filter)
+    }
+
+    fn list(&self) -> Result<Vec<Device>, AppError> {
+        self.memory.list()
+    }
+
+    fn move_task(&self, task_id: &str, to: Move) -> Result<(), AppError> {
+        self.memory.move_task(task_id, to)?;
+        self.persist();
+        Ok(())
+    }
+
+    fn cancel(&self, task_id: &str) -> Result<TaskRecord, AppError> {
+        let record = self.memory.cancel(task_id)?;
+        self.persist();
+        Ok(record)
+    }
+
+    fn flush(&self) -> Result<(), AppError> {
+        self.save()
+    }
+}
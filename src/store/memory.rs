@@ -0,0 +1,500 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::RwLock,
+};
+
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::DeviceInfo,
+    error::AppError,
+    model::{Device, ReportStatus, Task, User},
+};
+
+use super::{HistoryEntry, HistoryFilter, Move, Reported, TaskRecord, TaskStore};
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub(super) struct StoreData {
+    devices: HashMap<String, Device>,
+    tasks: HashMap<String, TaskRecord>,
+    // oldest first
+    #[serde(default)]
+    history: VecDeque<HistoryEntry>,
+}
+
+/// Keeps everything in memory, everything is lost on restart.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct MemoryStore {
+    data: RwLock<StoreData>,
+    history_limit: usize,
+}
+
+impl MemoryStore {
+    /// Create an empty store keeping at most `history_limit` reported tasks.
+    pub fn new(history_limit: usize) -> Self {
+        Self::from_data(StoreData::default(), history_limit)
+    }
+
+    pub(super) fn from_data(mut data: StoreData, history_limit: usize) -> Self {
+        truncate_history(&mut data.history, history_limit);
+
+        Self {
+            data: RwLock::new(data),
+            history_limit,
+        }
+    }
+
+    pub(super) fn snapshot(&self) -> Result<StoreData, AppError> {
+        Ok(self.data.read()?.clone())
+    }
+
+    /// Same as [`TaskStore::fetch_for_device`], also returning whether any task was delivered
+    /// for the first time.
+    pub(super) fn fetch(
+        &self,
+        device_id: &str,
+        user_id: &str,
+        replay_window: Option<Duration>,
+    ) -> Result<(Vec<Task>, bool), AppError> {
+        let mut data = self.data.write()?;
+        let StoreData {
+            ref devices,
+            ref mut tasks,
+            ..
+        } = *data;
+
+        let user = devices
+            .get(device_id)
+            .ok_or(AppError::DeviceNotFound(device_id.to_owned()))?
+            .users
+            .get(user_id)
+            .ok_or(AppError::UserNotFound(user_id.to_owned()))?;
+
+        let now = Utc::now();
+        let mut changed = false;
+        let mut fetched = vec![];
+
+        for task in &user.tasks {
+            let Some(record) = tasks.get_mut(&task.id).filter(|r| r.is_due(now)) else {
+                continue;
+            };
+
+            let deliver = match record.fetched_at {
+                None => {
+                    record.fetched_at = Some(now);
+                    changed = true;
+                    true
+                }
+                Some(fetched_at) => replay_window.is_some_and(|window| now - fetched_at < window),
+            };
+
+            if deliver {
+                fetched.push(task.clone());
+            }
+        }
+
+        Ok((fetched, changed))
+    }
+}
+
+impl TaskStore for MemoryStore {
+    fn register(&self, device: &DeviceInfo, user_id: &str) -> Result<bool, AppError> {
+        let mut data = self.data.write()?;
+
+        let device = data
+            .devices
+            .entry(device.id.clone())
+            .or_insert_with(|| device.clone().into());
+
+        if device.users.contains_key(user_id) {
+            return Ok(false);
+        }
+
+        device.users.insert(
+            user_id.to_owned(),
+            User {
+                id: user_id.to_owned(),
+                tasks: vec![],
+            },
+        );
+
+        Ok(true)
+    }
+
+    fn rename(&self, device_id: &str, name: &str) -> Result<bool, AppError> {
+        let mut data = self.data.write()?;
+
+        let Some(device) = data.devices.get_mut(device_id) else {
+            return Ok(false);
+        };
+        if device.name == name {
+            return Ok(false);
+        }
+
+        name.clone_into(&mut device.name);
+
+        Ok(true)
+    }
+
+    fn enqueue(&self, records: Vec<TaskRecord>) -> Result<(), AppError> {
+        let mut data = self.data.write()?;
+        insert_records(&mut data, records)
+    }
+
+    fn fetch_for_device(
+        &self,
+        device_id: &str,
+        user_id: &str,
+        replay_window: Option<Duration>,
+    ) -> Result<Vec<Task>, AppError> {
+        Ok(self.fetch(device_id, user_id, replay_window)?.0)
+    }
+
+    fn mark_reported(
+        &self,
+        task_id: &str,
+        status: &ReportStatus,
+        payload_summary: &str,
+        retry: Vec<TaskRecord>,
+    ) -> Result<Reported, AppError> {
+        let mut data = self.data.write()?;
+
+        if !data.tasks.contains_key(task_id) {
+            return data
+                .history
+                .iter()
+                .rev()
+                .find(|e| e.task_id == task_id)
+                .map(|previous| Reported::Again(previous.clone()))
+                .ok_or(AppError::TaskNotFound(task_id.to_owned()));
+        }
+
+        let next_attempt = retry.first().map(|r| r.task.id.clone());
+        insert_records(&mut data, retry)?;
+        let Some(record) = remove_task(&mut data, task_id) else {
+            return Err(AppError::TaskNotFound(task_id.to_owned()));
+        };
+
+        let entry = HistoryEntry {
+            retried_as: next_attempt.clone(),
+            ..HistoryEntry::new(record, status, payload_summary)
+        };
+        data.history.push_back(entry.clone());
+        truncate_history(&mut data.history, self.history_limit);
+
+        match next_attempt {
+            // the tasks waiting for this one wait for its next attempt instead
+            Some(next_attempt) => {
+                for dependency in data
+                    .tasks
+                    .values_mut()
+                    .filter_map(|r| r.depends_on.as_mut())
+                {
+                    if dependency.task_id == task_id {
+                        dependency.task_id.clone_from(&next_attempt);
+                    }
+                }
+            }
+            None => resolve_dependents(&mut data, task_id, Some(status)),
+        }
+
+        Ok(Reported::First(entry))
+    }
+
+    fn retry(&self, task_id: &str, retry: Vec<TaskRecord>) -> Result<bool, AppError> {
+        let mut data = self.data.write()?;
+
+        let index = data
+            .history
+            .iter()
+            .rposition(|e| e.task_id == task_id)
+            .ok_or(AppError::TaskNotFound(task_id.to_owned()))?;
+        if data.history[index].retried_as.is_some() {
+            return Ok(false);
+        }
+
+        let next_attempt = retry.first().map(|r| r.task.id.clone());
+        insert_records(&mut data, retry)?;
+        data.history[index].retried_as = next_attempt;
+
+        Ok(true)
+    }
+
+    fn get(&self, task_id: &str) -> Result<TaskRecord, AppError> {
+        self.data
+            .read()?
+            .tasks
+            .get(task_id)
+            .cloned()
+            .ok_or(AppError::TaskNotFound(task_id.to_owned()))
+    }
+
+    fn reported(&self, task_id: &str) -> Result<Option<HistoryEntry>, AppError> {
+        Ok(self
+            .data
+            .read()?
+            .history
+            .iter()
+            .rev()
+            .find(|e| e.task_id == task_id)
+            .cloned())
+    }
+
+    fn history(&self, filter: &HistoryFilter) -> Result<Vec<HistoryEntry>, AppError> {
+        Ok(self
+            .data
+            .read()?
+            .history
+            .iter()
+            .rev()
+            .filter(|e| filter.matches(e))
+            .cloned()
+            .collect())
+    }
+
+    fn list(&self) -> Result<Vec<Device>, AppError> {
+        Ok(self.data.read()?.devices.values().cloned().collect())
+    }
+
+    fn move_task(&self, task_id: &str, to: Move) -> Result<(), AppError> {
+        let mut data = self.data.write()?;
+        let StoreData {
+            ref mut devices,
+            ref tasks,
+            ..
+        } = *data;
+
+        let record = tasks
+            .get(task_id)
+            .ok_or(AppError::TaskNotFound(task_id.to_owned()))?;
+        if record.fetched_at.is_some() {
+            return Err(AppError::InvalidArgument(format!(
+                "task {task_id} was already handed to MAA"
+            )));
+        }
+
+        let queue = &mut devices
+            .get_mut(&record.device)
+            .ok_or(AppError::DeviceNotFound(record.device.clone()))?
+            .users
+            .get_mut(&record.user)
+            .ok_or(AppError::UserNotFound(record.user.clone()))?
+            .tasks;
+        // delivered tasks stay where they are, the others move around them, each along with
+        // its screenshot
+        let slots: Vec<_> = queue
+            .iter()
+            .enumerate()
+            .filter(|entry| {
+                tasks
+                    .get(&entry.1.id)
+                    .is_some_and(|r| r.fetched_at.is_none())
+            })
+            .map(|entry| entry.0)
+            .collect();
+        let mut blocks: Vec<Vec<Task>> = vec![];
+        for &i in &slots {
+            let task = queue[i].clone();
+            let capture_of = tasks.get(&task.id).and_then(|r| r.capture_of.as_deref());
+            match capture_of.and_then(|of| {
+                blocks
+                    .iter_mut()
+                    .find(|b| b.first().is_some_and(|t| t.id == of))
+            }) {
+                Some(block) => block.push(task),
+                None => blocks.push(vec![task]),
+            }
+        }
+        let block = blocks
+            .iter()
+            .position(|b| b.iter().any(|t| t.id == task_id))
+            .ok_or(AppError::TaskNotFound(task_id.to_owned()))?;
+
+        match to {
+            Move::Up if block > 0 => blocks.swap(block - 1, block),
+            Move::Down if block + 1 < blocks.len() => blocks.swap(block, block + 1),
+            Move::Front => {
+                let moved = blocks.remove(block);
+                blocks.insert(0, moved);
+            }
+            Move::Up | Move::Down => {}
+        }
+
+        for (slot, task) in slots.into_iter().zip(blocks.into_iter().flatten()) {
+            queue[slot] = task;
+        }
+
+        Ok(())
+    }
+
+    fn cancel(&self, task_id: &str) -> Result<TaskRecord, AppError> {
+        let mut data = self.data.write()?;
+
+        let record =
+            remove_task(&mut data, task_id).ok_or(AppError::TaskNotFound(task_id.to_owned()))?;
+
+        let captures: Vec<_> = data
+            .tasks
+            .values()
+            .filter(|r| r.capture_of.as_deref() == Some(task_id))
+            .map(|r| r.task.id.clone())
+            .collect();
+        for capture in captures {
+            remove_task(&mut data, &capture);
+            resolve_dependents(&mut data, &capture, None);
+        }
+
+        resolve_dependents(&mut data, task_id, None);
+
+        Ok(record)
+    }
+}
+
+/// Queue a batch of tasks as described in [`TaskStore::enqueue`].
+fn insert_records(data: &mut StoreData, mut records: Vec<TaskRecord>) -> Result<(), AppError> {
+    // a dependency on a retried task waits for its latest attempt
+    for dependency in records.iter_mut().filter_map(|r| r.depends_on.as_mut()) {
+        dependency.task_id = latest_attempt(data, &dependency.task_id);
+    }
+
+    // check every target first so that a failing batch leaves the queues untouched
+    for (i, record) in records.iter().enumerate() {
+        data.devices
+            .get(&record.device)
+            .ok_or(AppError::DeviceNotFound(record.device.clone()))?
+            .users
+            .get(&record.user)
+            .ok_or(AppError::UserNotFound(record.user.clone()))?;
+
+        if let Some(ref dependency) = record.depends_on {
+            let queued = data.tasks.contains_key(&dependency.task_id)
+                || records
+                    .iter()
+                    .take(i)
+                    .any(|r| r.task.id == dependency.task_id);
+            if !queued {
+                let reported = data
+                    .history
+                    .iter()
+                    .rev()
+                    .find(|e| e.task_id == dependency.task_id)
+                    .ok_or(AppError::TaskNotFound(dependency.task_id.clone()))?;
+                if !dependency.condition.is_met(&reported.status) {
+                    return Err(AppError::InvalidArgument(format!(
+                        "task {} was reported {}, the condition can never be met",
+                        dependency.task_id, reported.status
+                    )));
+                }
+            }
+        }
+    }
+
+    // dependencies already met are dropped
+    for record in &mut records {
+        if record
+            .depends_on
+            .as_ref()
+            .is_some_and(|d| data.history.iter().any(|e| e.task_id == d.task_id))
+        {
+            record.depends_on = None;
+        }
+    }
+
+    for record in records {
+        let StoreData {
+            ref mut devices,
+            ref mut tasks,
+            ..
+        } = *data;
+
+        if let Some(user) = devices
+            .get_mut(&record.device)
+            .and_then(|d| d.users.get_mut(&record.user))
+        {
+            let position = user
+                .tasks
+                .iter()
+                .position(|t| {
+                    tasks.get(&t.id).is_some_and(|queued| {
+                        queued.fetched_at.is_none() && queued.priority < record.priority
+                    })
+                })
+                .unwrap_or(user.tasks.len());
+            user.tasks.insert(position, record.task.clone());
+        }
+        tasks.insert(record.task.id.clone(), record);
+    }
+
+    Ok(())
+}
+
+/// The last attempt of a task that is still queued or reported, following its retries.
+fn latest_attempt(data: &StoreData, task_id: &str) -> String {
+    let mut latest = task_id.to_owned();
+    while let Some(next) = data
+        .history
+        .iter()
+        .rev()
+        .find(|e| e.task_id == latest)
+        .and_then(|e| e.retried_as.clone())
+    {
+        // a cancelled retry is not waited for
+        if !data.tasks.contains_key(&next) && !data.history.iter().any(|e| e.task_id == next) {
+            break;
+        }
+        latest = next;
+    }
+    latest
+}
+
+/// Remove a task from the tasks and from its user's queue.
+fn remove_task(data: &mut StoreData, task_id: &str) -> Option<TaskRecord> {
+    let record = data.tasks.remove(task_id)?;
+
+    if let Some(user) = data
+        .devices
+        .get_mut(&record.device)
+        .and_then(|d| d.users.get_mut(&record.user))
+    {
+        user.tasks.retain(|t| t.id != task_id);
+    }
+
+    Some(record)
+}
+
+/// Release the tasks depending on `task_id` if `status` meets their condition, drop them and
+/// their own dependents otherwise, `None` meaning the task was cancelled.
+fn resolve_dependents(data: &mut StoreData, task_id: &str, status: Option<&ReportStatus>) {
+    let dependents: Vec<_> = data
+        .tasks
+        .values()
+        .filter(|r| r.depends_on.as_ref().is_some_and(|d| d.task_id == task_id))
+        .map(|r| r.task.id.clone())
+        .collect();
+
+    for dependent in dependents {
+        let Some(record) = data.tasks.get_mut(&dependent) else {
+            continue;
+        };
+
+        let met = record
+            .depends_on
+            .as_ref()
+            .is_some_and(|d| status.is_some_and(|s| d.condition.is_met(s)));
+        if met {
+            record.depends_on = None;
+        } else {
+            tracing::info!("Dropping task {}, its condition on task {} was not met", dependent, task_id);
+            remove_task(data, &dependent);
+            resolve_dependents(data, &dependent, None);
+        }
+    }
+}
+
+fn truncate_history(history: &mut VecDeque<HistoryEntry>, limit: usize) {
+    while history.len() > limit {
+        history.pop_front();
+    }
+}
@@ -0,0 +1,454 @@
+//! In-process test harness: the axum `Router` is served on a local port, MAA is simulated by
+//! [`FakeMaa`] and the Telegram Bot API is replaced by [`MockTelegram`], which records every
+//! request the bot sends.
+
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    routing::post,
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use teloxide::{
+    dispatching::dialogue::InMemStorage,
+    types::{Me, Update, UpdateKind},
+    Bot,
+};
+use tokio::{net::TcpListener, task::JoinHandle, time::sleep};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    bot::{self, Dialog, HistoryFilters},
+    config::{Config, DEFAULT_HISTORY_LIMIT},
+    drain,
+    error::AppError,
+    model::Task,
+    outbox::{self, RetryPolicy},
+    router,
+    state::AppState,
+    store::{MemoryStore, TaskStore},
+};
+
+mod api;
+mod chain;
+mod cli;
+mod config;
+mod delivery;
+mod dialog;
+mod export;
+mod health;
+mod history;
+mod metrics;
+mod notify;
+mod priority;
+mod reload;
+mod report;
+mod retry;
+mod shutdown;
+mod store;
+
+pub const TG_USER_ID: i64 = 42;
+
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+
+/// Retries quickly enough for the tests to wait for them.
+pub const TEST_RETRY_POLICY: RetryPolicy = RetryPolicy {
+    max_attempts: 3,
+    initial_backoff: Duration::from_millis(10),
+    max_backoff: Duration::from_millis(100),
+};
+
+pub fn test_config() -> Config {
+    Config {
+        telegram_user_id: TG_USER_ID,
+        admin_token: Some(ADMIN_TOKEN.to_owned()),
+        ..Config::default()
+    }
+}
+
+/// A request received by the mock Bot API.
+#[derive(Clone, Debug)]
+pub struct SentRequest {
+    pub method: String,
+    pub body: Bytes,
+}
+
+impl SentRequest {
+    /// The `text` field of a JSON request such as `SendMessage`.
+    pub fn text(&self) -> Option<String> {
+        let body: Value = serde_json::from_slice(&self.body).ok()?;
+        body.get("text")?.as_str().map(str::to_owned)
+    }
+
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap_or_default()
+    }
+
+    /// Callback data of every button in the keyboard of the request.
+    pub fn callback_data(&self) -> Vec<String> {
+        self.json()["reply_markup"]["inline_keyboard"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .flat_map(|row| row.as_array().into_iter().flatten())
+            .filter_map(|button| button["callback_data"].as_str().map(str::to_owned))
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct MockTelegramState {
+    requests: Vec<SentRequest>,
+    /// Error responses to answer the next requests with, of the given method if any.
+    failures: Vec<(Option<String>, Value)>,
+}
+
+/// A local server answering like the Telegram Bot API.
+#[derive(Clone)]
+pub struct MockTelegram {
+    state: Arc<Mutex<MockTelegramState>>,
+    pub url: String,
+}
+
+impl MockTelegram {
+    pub async fn start() -> Self {
+        let state = Arc::new(Mutex::new(MockTelegramState::default()));
+
+        let app = Router::new()
+            .route("/:token/:method", post(mock_bot_api))
+            .with_state(Arc::clone(&state));
+
+        let url = serve(app).await;
+
+        Self { state, url }
+    }
+
+    /// Answer the next request with an error, e.g. `{"error_code": 400, "description": "..."}`.
+    pub fn fail_next(&self, error: Value) {
+        let mut state = self.state.lock().expect("lock not poisoned");
+        state.failures.push((None, error));
+    }
+
+    /// Answer the next request of `method`, e.g. `SendPhoto`, with an error.
+    pub fn fail_next_of(&self, method: &str, error: Value) {
+        let mut state = self.state.lock().expect("lock not poisoned");
+        state.failures.push((Some(method.to_owned()), error));
+    }
+
+    pub fn bot(&self) -> Bot {
+        Bot::new("test-token").set_api_url(self.url.parse().expect("valid mock url"))
+    }
+
+    pub fn requests(&self) -> Vec<SentRequest> {
+        self.state.lock().expect("lock not poisoned").requests.clone()
+    }
+
+    pub fn methods(&self) -> Vec<String> {
+        self.requests().into_iter().map(|r| r.method).collect()
+    }
+
+    pub fn texts(&self) -> Vec<String> {
+        self.requests().iter().filter_map(SentRequest::text).collect()
+    }
+}
+
+async fn mock_bot_api(
+    State(state): State<Arc<Mutex<MockTelegramState>>>,
+    Path((_token, method)): Path<(String, String)>,
+    body: Bytes,
+) -> Json<Value> {
+    let mut state = state.lock().expect("lock not poisoned");
+    if method == "GetMe" {
+        return Json(json!({
+            "ok": true,
+            "result": {
+                "id": 1,
+                "is_bot": true,
+                "first_name": "test",
+                "username": "test_bot",
+                "can_join_groups": false,
+                "can_read_all_group_messages": false,
+                "supports_inline_queries": false,
+            },
+        }));
+    }
+    let returns_true = method == "AnswerCallbackQuery";
+    let failure = state
+        .failures
+        .iter()
+        .position(|failure| failure.0.as_deref().is_none_or(|m| m == method));
+    state.requests.push(SentRequest { method, body });
+
+    if let Some(index) = failure {
+        let mut error = state.failures.remove(index).1;
+        error["ok"] = json!(false);
+        return Json(error);
+    }
+
+    if returns_true {
+        return Json(json!({ "ok": true, "result": true }));
+    }
+
+    Json(json!({
+        "ok": true,
+        "result": {
+            "message_id": state.requests.len(),
+            "date": 0,
+            "chat": { "id": TG_USER_ID, "type": "private", "first_name": "test" },
+            "text": "",
+        },
+    }))
+}
+
+/// The bot's HTTP server started against a [`MockTelegram`].
+pub struct TestApp {
+    pub url: String,
+    pub app_state: Arc<AppState>,
+    pub telegram: MockTelegram,
+    outbox_shutdown: CancellationToken,
+    outbox_run: JoinHandle<()>,
+}
+
+impl TestApp {
+    pub async fn start() -> Self {
+        Self::start_with(&test_config(), Arc::new(MemoryStore::new(DEFAULT_HISTORY_LIMIT))).await
+    }
+
+    pub async fn start_with(config: &Config, store: Arc<dyn TaskStore>) -> Self {
+        let telegram = MockTelegram::start().await;
+
+        let app_state = Arc::new(AppState::new(config, telegram.bot(), store));
+
+        let url = serve(router(Arc::clone(&app_state))).await;
+        let outbox_shutdown = CancellationToken::new();
+        let outbox_run = tokio::spawn(outbox::run(
+            Arc::clone(&app_state),
+            TEST_RETRY_POLICY,
+            outbox_shutdown.clone(),
+        ));
+
+        Self {
+            url,
+            app_state,
+            telegram,
+            outbox_shutdown,
+            outbox_run,
+        }
+    }
+
+    /// Stop delivering notifications and persist the state, like the bot does on exit.
+    pub async fn shut_down(self) -> Result<(), AppError> {
+        drain(&self.app_state, &self.outbox_shutdown, self.outbox_run).await
+    }
+
+    /// Wait until every queued report has been sent to the notifiers.
+    pub async fn notified(&self) {
+        for _ in 0..500 {
+            if self.app_state.outbox().pending() == 0 {
+                return;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        panic!("notifications still pending");
+    }
+
+    /// A request to the admin API, authenticated with [`ADMIN_TOKEN`].
+    pub fn api(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        reqwest::Client::new()
+            .request(method, format!("{}{path}", self.url))
+            .bearer_auth(ADMIN_TOKEN)
+    }
+
+    pub fn chat(&self) -> TestChat {
+        TestChat {
+            app_state: Arc::clone(&self.app_state),
+            dialogs: InMemStorage::new(),
+            history_filters: Arc::new(HistoryFilters::default()),
+            updates: AtomicI32::new(1),
+        }
+    }
+
+    pub fn maa(&self, device: &str, user: &str) -> FakeMaa {
+        FakeMaa {
+            url: self.url.clone(),
+            device: device.to_owned(),
+            user: user.to_owned(),
+            client: reqwest::Client::new(),
+            seen: HashSet::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct GetTaskBody {
+    tasks: Vec<Task>,
+}
+
+/// Simulates the MAA remote control client: it polls `/get`, skips task ids already seen in
+/// this session like MAA does, and reports results to `/report`.
+pub struct FakeMaa {
+    url: String,
+    device: String,
+    user: String,
+    client: reqwest::Client,
+    seen: HashSet<String>,
+}
+
+impl FakeMaa {
+    /// Poll `/get` and return the raw task list.
+    pub async fn poll(&self) -> Vec<Task> {
+        let response = self
+            .client
+            .post(format!("{}/get", self.url))
+            .json(&json!({ "user": self.user, "device": self.device }))
+            .send()
+            .await
+            .expect("get request sent");
+        assert!(response.status().is_success(), "get failed: {}", response.status());
+
+        response
+            .json::<GetTaskBody>()
+            .await
+            .expect("valid get response")
+            .tasks
+    }
+
+    /// Poll `/get` and return only the tasks not seen before in this session.
+    pub async fn poll_new(&mut self) -> Vec<Task> {
+        let tasks = self.poll().await;
+
+        tasks
+            .into_iter()
+            .filter(|t| self.seen.insert(t.id.clone()))
+            .collect()
+    }
+
+    pub async fn report(&self, task_id: &str, status: &str, payload: &str) -> reqwest::StatusCode {
+        self.client
+            .post(format!("{}/report", self.url))
+            .json(&json!({
+                "user": self.user,
+                "device": self.device,
+                "task": task_id,
+                "status": status,
+                "payload": payload,
+            }))
+            .send()
+            .await
+            .expect("report request sent")
+            .status()
+    }
+
+    /// Poll once and report every new task with the result computed by `execute`.
+    pub async fn run_once<F>(&mut self, mut execute: F) -> Vec<Task>
+    where
+        F: FnMut(&Task) -> (String, String),
+    {
+        let tasks = self.poll_new().await;
+
+        for task in &tasks {
+            let (status, payload) = execute(task);
+            let code = self.report(&task.id, &status, &payload).await;
+            assert!(code.is_success(), "report failed: {code}");
+        }
+
+        tasks
+    }
+}
+
+/// The configured Telegram user talking to the bot: updates go through the same handlers as with
+/// the dispatcher, keeping the dialog state between them.
+pub struct TestChat {
+    app_state: Arc<AppState>,
+    dialogs: Arc<InMemStorage<Dialog>>,
+    history_filters: Arc<HistoryFilters>,
+    updates: AtomicI32,
+}
+
+impl TestChat {
+    pub async fn command(&self, command: &str) {
+        let message = serde_json::from_value(message(0, command)).expect("valid message");
+        self.send(UpdateKind::Message(message)).await;
+    }
+
+    /// Press the button with `data` under the message `message_id`.
+    pub async fn press(&self, message_id: i32, data: &str) {
+        let query = serde_json::from_value(json!({
+            "id": "query",
+            "from": { "id": TG_USER_ID, "is_bot": false, "first_name": "test" },
+            "chat_instance": "chat",
+            "message": message(message_id, ""),
+            "data": data,
+        }))
+        .expect("valid callback query");
+        self.send(UpdateKind::CallbackQuery(query)).await;
+    }
+
+    async fn send(&self, kind: UpdateKind) {
+        let update = Update {
+            id: self.updates.fetch_add(1, Ordering::Relaxed),
+            kind,
+        };
+        let me: Me = serde_json::from_value(json!({
+            "id": 1,
+            "is_bot": true,
+            "first_name": "test",
+            "username": "test_bot",
+            "can_join_groups": false,
+            "can_read_all_group_messages": false,
+            "supports_inline_queries": false,
+        }))
+        .expect("valid bot user");
+
+        let result = bot::schema()
+            .dispatch(dptree::deps![
+                update,
+                me,
+                self.app_state.bot.clone(),
+                Arc::clone(&self.app_state),
+                Arc::clone(&self.dialogs),
+                Arc::clone(&self.history_filters)
+            ])
+            .await;
+        match result {
+            ControlFlow::Break(result) => result.expect("update handled"),
+            ControlFlow::Continue(_) => panic!("update not handled"),
+        }
+    }
+}
+
+fn message(message_id: i32, text: &str) -> Value {
+    json!({
+        "message_id": message_id,
+        "date": 0,
+        "chat": { "id": TG_USER_ID, "type": "private", "first_name": "test" },
+        "from": { "id": TG_USER_ID, "is_bot": false, "first_name": "test" },
+        "text": text,
+    })
+}
+
+/// Serve `app` on a random local port and return its base url.
+async fn serve(app: Router) -> String {
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .await
+        .expect("bind local port");
+    let address = listener.local_addr().expect("local address");
+
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.expect("serve");
+    });
+
+    format!("http://{address}")
+}
@@ -0,0 +1,157 @@
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+use crate::model::{Task, TaskType};
+
+use super::{TestApp, ADMIN_TOKEN};
+
+#[tokio::test]
+async fn api_requires_admin_token() {
+    let app = TestApp::start().await;
+    let client = reqwest::Client::new();
+    let url = format!("{}/api/devices", app.url);
+
+    let missing = client.get(&url).send().await.unwrap();
+    assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+
+    let wrong = client.get(&url).bearer_auth("nope").send().await.unwrap();
+    assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+
+    let right = client
+        .get(&url)
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(right.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn api_enqueues_tasks_for_maa() {
+    let app = TestApp::start().await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    let devices: Vec<Value> = app
+        .api(Method::GET, "/api/devices")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0]["id"], "device-1");
+
+    let response = app
+        .api(Method::POST, "/api/devices/device-1/users/user-1/tasks")
+        .json(&json!({ "type": "LinkStart-Combat" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created: Vec<Task> = response.json().await.unwrap();
+
+    let polled = maa.poll_new().await;
+    assert_eq!(
+        polled.iter().map(|t| &t.id).collect::<Vec<_>>(),
+        created.iter().map(|t| &t.id).collect::<Vec<_>>()
+    );
+    assert!(
+        matches!(polled[0].task_type, TaskType::LinkStartCombat),
+        "unexpected task {:?}",
+        polled[0]
+    );
+}
+
+#[tokio::test]
+async fn api_rejects_unknown_targets_and_types() {
+    let app = TestApp::start().await;
+    app.maa("device-1", "user-1").poll().await;
+
+    let unknown_device = app
+        .api(Method::POST, "/api/devices/device-2/users/user-1/tasks")
+        .json(&json!({ "type": "LinkStart-Combat" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(unknown_device.status(), StatusCode::NOT_FOUND);
+
+    let unknown_type = app
+        .api(Method::POST, "/api/devices/device-1/users/user-1/tasks")
+        .json(&json!({ "type": "LinkStart-Nothing" }))
+        .send()
+        .await
+        .unwrap();
+    assert!(
+        unknown_type.status().is_client_error(),
+        "unexpected status {}",
+        unknown_type.status()
+    );
+}
+
+#[tokio::test]
+async fn api_tracks_and_cancels_tasks() {
+    let app = TestApp::start().await;
+    let maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    let tasks = app
+        .app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartMall)
+        .unwrap();
+    let task_path = format!("/api/tasks/{}", tasks[0].id);
+    let capture_path = format!("/api/tasks/{}", tasks[1].id);
+
+    let pending: Value = app
+        .api(Method::GET, &task_path)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(pending["state"], "pending");
+
+    let cancelled = app.api(Method::DELETE, &capture_path).send().await.unwrap();
+    assert_eq!(cancelled.status(), StatusCode::OK);
+    let again = app.api(Method::DELETE, &capture_path).send().await.unwrap();
+    assert_eq!(again.status(), StatusCode::NOT_FOUND);
+
+    let polled = maa.poll().await;
+    assert_eq!(polled.len(), 1, "cancelled task should not be delivered");
+    maa.report(&polled[0].id, "SUCCESS", "").await;
+
+    let reported: Value = app
+        .api(Method::GET, &task_path)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(reported["state"], "reported");
+    assert_eq!(reported["status"], "SUCCESS");
+
+    // the screenshot queued after a task goes with it
+    let combat = app
+        .app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartCombat)
+        .unwrap();
+    let cancelled_combat = app
+        .api(Method::DELETE, &format!("/api/tasks/{}", combat[0].id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(cancelled_combat.status(), StatusCode::OK);
+    let capture = app
+        .api(Method::GET, &format!("/api/tasks/{}", combat[1].id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(capture.status(), StatusCode::NOT_FOUND);
+    assert!(
+        maa.poll().await.iter().all(|t| t.id != combat[1].id),
+        "the capture of a cancelled task should not be delivered"
+    );
+}
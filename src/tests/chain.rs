@@ -0,0 +1,218 @@
+use std::sync::Arc;
+
+use reqwest::{Method, StatusCode};
+use serde_json::json;
+
+use crate::{
+    config::{Config, TaskRetryConfig, DEFAULT_HISTORY_LIMIT},
+    model::{Task, TaskType},
+    store::{Condition, Dependency, MemoryStore},
+};
+
+use super::{test_config, TestApp};
+
+fn types(tasks: &[Task]) -> Vec<String> {
+    tasks.iter().map(|t| t.task_type.to_string()).collect()
+}
+
+#[tokio::test]
+async fn dependent_task_waits_for_its_condition() {
+    let app = TestApp::start().await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    let wake_up = app
+        .app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartWakeUp)
+        .unwrap();
+    let dependency = Dependency {
+        task_id: wake_up[0].id.clone(),
+        condition: Condition::Success,
+    };
+    app.app_state
+        .append_dependent_task("device-1", "user-1", &TaskType::LinkStartCombat, &dependency)
+        .unwrap();
+
+    let first = maa.poll_new().await;
+    assert_eq!(types(&first), vec!["LinkStart-WakeUp", "CaptureImage"]);
+
+    assert!(
+        maa.report(&first[0].id, "SUCCESS", "").await.is_success(),
+        "report should be accepted"
+    );
+    let released = maa.poll_new().await;
+    assert_eq!(types(&released), vec!["LinkStart-Combat", "CaptureImage"]);
+}
+
+#[tokio::test]
+async fn dependent_task_waits_for_the_retries() {
+    let config = Config {
+        task_retries: Some(vec![TaskRetryConfig {
+            task_type: TaskType::LinkStartWakeUp,
+            max_attempts: 2,
+            backoff_secs: 0,
+        }]),
+        ..test_config()
+    };
+    let app = TestApp::start_with(&config, Arc::new(MemoryStore::new(DEFAULT_HISTORY_LIMIT))).await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    let wake_up = app
+        .app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartWakeUp)
+        .unwrap();
+    let dependency = Dependency {
+        task_id: wake_up[0].id.clone(),
+        condition: Condition::Success,
+    };
+    app.app_state
+        .append_dependent_task("device-1", "user-1", &TaskType::LinkStartCombat, &dependency)
+        .unwrap();
+
+    let first = maa.poll_new().await;
+    maa.report(&first[0].id, "FAILED", "").await;
+    let retried = maa.poll_new().await;
+    assert_eq!(
+        types(&retried),
+        vec!["LinkStart-WakeUp", "CaptureImage"],
+        "only the retry should be handed out"
+    );
+
+    maa.report(&retried[0].id, "SUCCESS", "").await;
+    let released = maa.poll_new().await;
+    assert_eq!(types(&released), vec!["LinkStart-Combat", "CaptureImage"]);
+}
+
+#[tokio::test]
+async fn dependency_on_a_retried_task_waits_for_the_retry() {
+    let config = Config {
+        task_retries: Some(vec![TaskRetryConfig {
+            task_type: TaskType::LinkStartWakeUp,
+            max_attempts: 2,
+            backoff_secs: 0,
+        }]),
+        ..test_config()
+    };
+    let app = TestApp::start_with(&config, Arc::new(MemoryStore::new(DEFAULT_HISTORY_LIMIT))).await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    let wake_up = app
+        .app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartWakeUp)
+        .unwrap();
+    let first = maa.poll_new().await;
+    maa.report(&first[0].id, "FAILED", "").await;
+
+    // chained on the first attempt only after it failed
+    let dependency = Dependency {
+        task_id: wake_up[0].id.clone(),
+        condition: Condition::Success,
+    };
+    app.app_state
+        .append_dependent_task("device-1", "user-1", &TaskType::LinkStartCombat, &dependency)
+        .expect("the retry may still succeed");
+
+    let retried = maa.poll_new().await;
+    assert_eq!(types(&retried), vec!["LinkStart-WakeUp", "CaptureImage"]);
+    maa.report(&retried[0].id, "SUCCESS", "").await;
+    let released = maa.poll_new().await;
+    assert_eq!(types(&released), vec!["LinkStart-Combat", "CaptureImage"]);
+}
+
+#[tokio::test]
+async fn unmet_condition_drops_the_chain() {
+    let app = TestApp::start().await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    let combat = app
+        .app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartCombat)
+        .unwrap();
+    // a screenshot only on failure, followed by another task
+    let on_failure = app
+        .app_state
+        .append_dependent_task(
+            "device-1",
+            "user-1",
+            &TaskType::CaptureImageNow,
+            &Dependency {
+                task_id: combat[0].id.clone(),
+                condition: Condition::Failure,
+            },
+        )
+        .unwrap();
+    app.app_state
+        .append_dependent_task(
+            "device-1",
+            "user-1",
+            &TaskType::LinkStartMall,
+            &Dependency {
+                task_id: on_failure[0].id.clone(),
+                condition: Condition::Always,
+            },
+        )
+        .unwrap();
+
+    maa.run_once(|_| ("SUCCESS".to_owned(), String::new()))
+        .await;
+
+    assert!(maa.poll_new().await.is_empty(), "nothing should be released");
+    assert!(
+        app.app_state.store().list().unwrap()[0].users["user-1"]
+            .tasks
+            .is_empty(),
+        "dependent tasks should be dropped"
+    );
+}
+
+#[tokio::test]
+async fn api_checks_dependencies() {
+    let app = TestApp::start().await;
+    let maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    let unknown = app
+        .api(Method::POST, "/api/devices/device-1/users/user-1/tasks")
+        .json(&json!({
+            "type": "LinkStart-Combat",
+            "depends_on": { "task": "no-such-task" },
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(unknown.status(), StatusCode::NOT_FOUND);
+
+    let task = app
+        .app_state
+        .append_task("device-1", "user-1", &TaskType::CaptureImage)
+        .unwrap()
+        .remove(0);
+    maa.poll().await;
+    maa.report(&task.id, "FAILED", "").await;
+
+    let never = app
+        .api(Method::POST, "/api/devices/device-1/users/user-1/tasks")
+        .json(&json!({
+            "type": "LinkStart-Combat",
+            "depends_on": { "task": task.id, "condition": "success" },
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(never.status(), StatusCode::BAD_REQUEST);
+
+    let met = app
+        .api(Method::POST, "/api/devices/device-1/users/user-1/tasks")
+        .json(&json!({
+            "type": "LinkStart-Combat",
+            "depends_on": { "task": task.id, "condition": "failure" },
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(met.status(), StatusCode::CREATED);
+    assert_eq!(maa.poll().await.len(), 2, "met dependency should be released");
+}
@@ -0,0 +1,94 @@
+use std::{env::temp_dir, fs::remove_file};
+
+use serde_json::Value;
+
+use crate::{
+    cli::run,
+    config::{AppSubcommand, Config, DeviceInfo, DEFAULT_HISTORY_LIMIT},
+    export::ExportFormat,
+    model::{ReportStatus, TaskType},
+    store::{FileStore, TaskStore},
+};
+
+use super::test_config;
+
+fn run_to_string(command: AppSubcommand, config: &Config) -> String {
+    let mut out = vec![];
+    run(command, config, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn commands_work_on_the_state_file() {
+    let path = temp_dir().join(format!("maa-tgbot-{}.json", uuid::Uuid::new_v4()));
+    let path = path.to_str().unwrap();
+    let config = Config {
+        state_file: Some(path.to_owned()),
+        ..test_config()
+    };
+
+    let store = FileStore::open(path, DEFAULT_HISTORY_LIMIT).unwrap();
+    store
+        .register(
+            &DeviceInfo {
+                id: "device-1".to_owned(),
+                name: "Phone".to_owned(),
+            },
+            "user-1",
+        )
+        .unwrap();
+    drop(store);
+
+    let enqueued = run_to_string(
+        AppSubcommand::Enqueue {
+            device: "device-1".to_owned(),
+            user: "user-1".to_owned(),
+            task: TaskType::LinkStartMall,
+        },
+        &config,
+    );
+    assert!(enqueued.starts_with("Queued LinkStart-Mall"), "{enqueued}");
+    assert!(enqueued.contains("Queued CaptureImage"), "{enqueued}");
+
+    let devices = run_to_string(AppSubcommand::ListDevices, &config);
+    let lines: Vec<_> = devices.lines().collect();
+    assert_eq!(lines[0], "device-1 (Phone)");
+    assert_eq!(lines[1], "  user-1: 2 queued tasks");
+    assert!(lines[2].starts_with("    LinkStart-Mall ("), "{devices}");
+
+    let maa_store = FileStore::open(path, DEFAULT_HISTORY_LIMIT).unwrap();
+    let task = maa_store.fetch_for_device("device-1", "user-1", None).unwrap()[0].clone();
+    maa_store
+        .mark_reported(&task.id, &ReportStatus::Success, "", vec![])
+        .unwrap();
+    drop(maa_store);
+
+    let exported = run_to_string(
+        AppSubcommand::ExportHistory {
+            format: ExportFormat::Json,
+            output: None,
+            device: Some("device-1".to_owned()),
+            task_type: None,
+            from: None,
+            to: None,
+        },
+        &config,
+    );
+    let history: Value = serde_json::from_str(&exported).unwrap();
+    assert_eq!(history.as_array().unwrap().len(), 1);
+    assert_eq!(history[0]["task_id"], task.id.as_str());
+
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn commands_need_a_state_file() {
+    let mut out = vec![];
+
+    let result = run(AppSubcommand::ListDevices, &test_config(), &mut out);
+
+    assert!(
+        result.is_err(),
+        "there is no state to list without a state file"
+    );
+}
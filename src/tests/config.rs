@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+
+use crate::{
+    config::{
+        Config, DeviceInfo, TaskRetryConfig, ADMIN_TOKEN_VAR, MAX_RETRY_BACKOFF_SECS,
+        TELEGRAM_BOT_TOKEN_VAR,
+    },
+    error::AppError,
+    model::TaskType,
+};
+
+use super::test_config;
+
+fn config_error(result: Result<Config, AppError>) -> String {
+    match result {
+        Err(AppError::ConfigError(e)) => e,
+        Err(e) => panic!("unexpected error: {e}"),
+        Ok(config) => panic!("config accepted: {config:?}"),
+    }
+}
+
+#[test]
+fn parses_json_toml_and_yaml() {
+    let json = r#"{
+        "port": 8080,
+        "telegram_bot_token": "token",
+        "telegram_user_id": 42,
+        "devices": [{ "id": "device-1", "name": "Phone" }]
+    }"#;
+    let toml = r#"
+        port = 8080
+        telegram_bot_token = "token"
+        telegram_user_id = 42
+
+        [[devices]]
+        id = "device-1"
+        name = "Phone"
+    "#;
+    let yaml = "
+port: 8080
+telegram_bot_token: token
+telegram_user_id: 42
+devices:
+  - id: device-1
+    name: Phone
+";
+
+    for (file, content) in [
+        ("config.json", json),
+        ("config.toml", toml),
+        ("config.yaml", yaml),
+        ("config.yml", yaml),
+    ] {
+        let config = Config::parse(file, content).unwrap();
+        assert_eq!(config.port, 8080, "{file}");
+        assert_eq!(config.telegram_bot_token, "token", "{file}");
+        assert_eq!(config.telegram_user_id, 42, "{file}");
+        assert_eq!(
+            config.devices.unwrap()[0],
+            DeviceInfo {
+                id: "device-1".to_owned(),
+                name: "Phone".to_owned(),
+            },
+            "{file}"
+        );
+    }
+}
+
+#[test]
+fn parse_errors_have_line_and_column() {
+    let json = "{\n  \"port\": \"8080\"\n}";
+    let json_error = config_error(Config::parse("config.json", json));
+    assert!(json_error.starts_with("config.json:2:"), "{json_error}");
+    assert!(json_error.contains("invalid type"), "{json_error}");
+
+    let toml = "port = 8080\ntelegram_user_id = \"42\"\n";
+    let toml_error = config_error(Config::parse("config.toml", toml));
+    assert!(toml_error.starts_with("config.toml:2:20:"), "{toml_error}");
+
+    let yaml = "port: 8080\ntelegram_user_id: [42]\n";
+    let yaml_error = config_error(Config::parse("config.yaml", yaml));
+    assert!(yaml_error.starts_with("config.yaml:2:"), "{yaml_error}");
+}
+
+#[test]
+fn validation_reports_every_problem() {
+    let config = Config {
+        telegram_bot_token: " ".to_owned(),
+        admin_token: Some(String::new()),
+        log_level: Some("info,=".to_owned()),
+        log_retention_days: Some(0),
+        dialog_timeout_secs: Some(0),
+        devices: Some(vec![
+            DeviceInfo {
+                id: "device-1".to_owned(),
+                name: "Phone".to_owned(),
+            },
+            DeviceInfo {
+                id: "device-1".to_owned(),
+                name: "Tablet".to_owned(),
+            },
+        ]),
+        task_retries: Some(vec![TaskRetryConfig {
+            task_type: TaskType::LinkStartCombat,
+            max_attempts: 0,
+            backoff_secs: MAX_RETRY_BACKOFF_SECS + 1,
+        }]),
+        ..test_config()
+    };
+
+    let error = config_error(config.validate().map(|()| config.clone()));
+    let problems: Vec<_> = error.lines().collect();
+    assert_eq!(problems.len(), 8, "{error}");
+    assert!(problems[0].contains("telegram_bot_token"));
+    assert!(problems[1].contains("admin_token"));
+    assert!(problems[2].contains("log_level"));
+    assert!(problems[3].contains("log_retention_days"));
+    assert!(problems[4].contains("dialog_timeout_secs"));
+    assert!(problems[5].contains("duplicate device id device-1"));
+    assert!(problems[6].contains("max_attempts of LinkStart-Combat"));
+    assert!(problems[7].contains("backoff_secs of LinkStart-Combat"));
+}
+
+#[test]
+fn retry_backoff_is_capped() {
+    let retry = TaskRetryConfig {
+        task_type: TaskType::LinkStartCombat,
+        max_attempts: u32::MAX,
+        backoff_secs: 60,
+    };
+
+    assert_eq!(retry.backoff(2).num_seconds(), 120);
+    assert_eq!(
+        retry.backoff(u32::MAX).num_seconds(),
+        i64::try_from(MAX_RETRY_BACKOFF_SECS).unwrap()
+    );
+}
+
+#[test]
+fn secrets_are_overridden_by_the_environment() {
+    let mut config =
+        Config::parse("config.json", r#"{ "port": 8080, "telegram_user_id": 42 }"#).unwrap();
+    assert!(config.validate().is_err(), "token is missing");
+
+    let vars = HashMap::from([
+        (TELEGRAM_BOT_TOKEN_VAR, "env-token"),
+        (ADMIN_TOKEN_VAR, "env-admin-token"),
+    ]);
+    config.override_secrets(|name| vars.get(name).map(|&v| v.to_owned()));
+
+    assert_eq!(config.telegram_bot_token, "env-token");
+    assert_eq!(config.admin_token.as_deref(), Some("env-admin-token"));
+    config.validate().unwrap();
+}
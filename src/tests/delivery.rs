@@ -0,0 +1,117 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::time::sleep;
+
+use crate::{
+    config::{Config, DEFAULT_HISTORY_LIMIT},
+    model::TaskType,
+    store::MemoryStore,
+};
+
+use super::{test_config, TestApp};
+
+#[tokio::test]
+async fn delivered_task_is_not_sent_again() {
+    let app = TestApp::start().await;
+    let maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartWakeUp)
+        .unwrap();
+
+    assert_eq!(
+        maa.poll().await.len(),
+        2,
+        "task and capture should be delivered"
+    );
+    assert!(
+        maa.poll().await.is_empty(),
+        "delivered tasks should not be sent again"
+    );
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::CaptureImage)
+        .unwrap();
+    let tasks = maa.poll().await;
+    assert_eq!(tasks.len(), 1, "only the new task should be delivered");
+    assert!(
+        matches!(tasks[0].task_type, TaskType::CaptureImage),
+        "unexpected task {:?}",
+        tasks[0]
+    );
+}
+
+#[tokio::test]
+async fn maa_restart_does_not_replay_tasks() {
+    let app = TestApp::start().await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartMission)
+        .unwrap();
+    let tasks = maa.poll_new().await;
+    maa.report(&tasks[0].id, "SUCCESS", "").await;
+
+    // a fresh session has no memory of the task ids it has already seen
+    let restarted = app.maa("device-1", "user-1");
+    assert!(
+        restarted.poll().await.is_empty(),
+        "neither reported nor delivered tasks should be replayed"
+    );
+}
+
+#[tokio::test]
+async fn replay_window_resends_unreported_tasks() {
+    let config = Config {
+        task_replay_window_secs: Some(1),
+        ..test_config()
+    };
+    let app = TestApp::start_with(&config, Arc::new(MemoryStore::new(DEFAULT_HISTORY_LIMIT))).await;
+    let maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartRecruiting)
+        .unwrap();
+    let tasks = maa.poll().await;
+    assert_eq!(tasks.len(), 2, "task and capture should be delivered");
+
+    maa.report(&tasks[0].id, "SUCCESS", "").await;
+
+    let replayed = maa.poll().await;
+    assert_eq!(
+        replayed.len(),
+        1,
+        "only the unreported task should be replayed"
+    );
+    assert_eq!(replayed[0].id, tasks[1].id);
+
+    sleep(Duration::from_millis(1100)).await;
+    assert!(
+        maa.poll().await.is_empty(),
+        "tasks should not be replayed after the window"
+    );
+}
+
+#[tokio::test]
+async fn oversized_replay_window_never_expires() {
+    let config = Config {
+        // fits in an i64 but not in a chrono Duration
+        task_replay_window_secs: Some(1 << 62),
+        ..test_config()
+    };
+    let app = TestApp::start_with(&config, Arc::new(MemoryStore::new(DEFAULT_HISTORY_LIMIT))).await;
+    let maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartRecruiting)
+        .unwrap();
+    let tasks = maa.poll().await;
+    assert_eq!(maa.poll().await.len(), tasks.len(), "tasks should be replayed");
+
+    // applied on reload as well
+    app.app_state.apply_config(&config).unwrap();
+}
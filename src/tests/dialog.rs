@@ -0,0 +1,171 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::time::sleep;
+
+use crate::{
+    config::{Config, DEFAULT_HISTORY_LIMIT},
+    model::TaskType,
+    store::MemoryStore,
+};
+
+use super::{test_config, SentRequest, TestApp};
+
+/// Callback data of every button in the keyboard of a request, without the dialog nonce.
+fn buttons(request: &SentRequest) -> Vec<String> {
+    request.callback_data()
+        .into_iter()
+        .filter_map(|data| data.split_once(':').map(|(_, button)| button.to_owned()))
+        .collect()
+}
+
+/// The callback data sent by pressing `button` in the keyboard of a request.
+fn button(request: &SentRequest, button: &str) -> String {
+    request.callback_data()
+        .into_iter()
+        .find(|data| data.split_once(':').is_some_and(|(_, b)| b == button))
+        .unwrap_or_else(|| panic!("no {button} button"))
+}
+
+fn expired(app: &TestApp) -> bool {
+    let requests = app.telegram.requests();
+    let answer = requests
+        .iter()
+        .rfind(|r| r.method == "AnswerCallbackQuery")
+        .expect("the press should be answered");
+
+    answer.json()["text"] == "This menu expired."
+        && requests
+            .last()
+            .is_some_and(|r| r.method == "EditMessageReplyMarkup")
+}
+
+fn last(app: &TestApp) -> SentRequest {
+    app.telegram.requests().pop().expect("a request was sent")
+}
+
+#[tokio::test]
+async fn append_dialog_goes_back_and_edits_its_message() {
+    let app = TestApp::start().await;
+    app.maa("device-1", "user-1").poll().await;
+    let maa = app.maa("device-2", "user-1");
+    maa.poll().await;
+    let chat = app.chat();
+
+    chat.command("/appendtask").await;
+    let devices = last(&app);
+    assert_eq!(devices.method, "SendMessage");
+    assert_eq!(
+        buttons(&devices),
+        vec!["d:device-1", "d:device-2", "cancel"]
+    );
+
+    chat.press(7, &button(&devices, "d:device-1")).await;
+    let users = last(&app);
+    assert_eq!(users.method, "EditMessageText");
+    assert_eq!(
+        users.json()["message_id"],
+        7,
+        "the dialog message should be edited"
+    );
+    assert_eq!(buttons(&users), vec!["u:user-1", "back", "cancel"]);
+
+    chat.press(7, &button(&users, "back")).await;
+    let devices_again = last(&app);
+    assert_eq!(
+        buttons(&devices_again),
+        vec!["d:device-1", "d:device-2", "cancel"]
+    );
+
+    chat.press(7, &button(&devices_again, "d:device-2")).await;
+    chat.press(7, &button(&last(&app), "u:user-1")).await;
+    let tasks = last(&app);
+    let task_buttons = buttons(&tasks);
+    assert!(task_buttons.contains(&"t:LinkStart-Combat".to_owned()));
+    assert_eq!(task_buttons[task_buttons.len() - 2..], ["back", "cancel"]);
+
+    chat.press(7, &button(&tasks, "t:LinkStart-Combat")).await;
+    let added = last(&app);
+    assert_eq!(
+        added.text().as_deref(),
+        Some("Task LinkStart-Combat added for device device-2, user user-1.")
+    );
+    assert!(buttons(&added).is_empty(), "the keyboard should be removed");
+    assert!(matches!(
+        maa.poll().await[0].task_type,
+        TaskType::LinkStartCombat
+    ));
+}
+
+#[tokio::test]
+async fn cancelled_dialog_keyboard_expires() {
+    let app = TestApp::start().await;
+    app.maa("device-1", "user-1").poll().await;
+    let maa = app.maa("device-1", "user-1");
+    let chat = app.chat();
+
+    chat.command("/appendtask").await;
+    let tasks = last(&app);
+    chat.press(3, &button(&tasks, "cancel")).await;
+    assert_eq!(last(&app).text().as_deref(), Some("Cancelled."));
+    assert!(buttons(&last(&app)).is_empty());
+
+    // a button of the cancelled dialog
+    chat.press(3, &button(&tasks, "t:LinkStart-Combat")).await;
+    assert!(expired(&app), "the press should be refused");
+    assert!(maa.poll().await.is_empty(), "no task should be queued");
+}
+
+#[tokio::test]
+async fn unknown_task_button_expires() {
+    let app = TestApp::start().await;
+    app.maa("device-1", "user-1").poll().await;
+    let maa = app.maa("device-1", "user-1");
+    let chat = app.chat();
+
+    chat.command("/appendtask").await;
+    let combat = button(&last(&app), "t:LinkStart-Combat");
+    chat.press(1, &combat.replace("LinkStart-Combat", "NoSuchTask")).await;
+    assert!(expired(&app), "the press should be refused");
+    assert!(maa.poll().await.is_empty(), "no task should be queued");
+}
+
+#[tokio::test]
+async fn new_dialog_expires_the_previous_keyboard() {
+    let app = TestApp::start().await;
+    app.maa("device-1", "user-1").poll().await;
+    let maa = app.maa("device-1", "user-1");
+    let chat = app.chat();
+
+    chat.command("/appendtask").await;
+    let first = last(&app);
+    chat.command("/appendtask").await;
+    let second = last(&app);
+
+    chat.press(1, &button(&first, "t:LinkStart-Combat")).await;
+    assert!(expired(&app), "the first keyboard should be refused");
+
+    chat.press(2, &button(&second, "t:LinkStart-Mall")).await;
+    let tasks = maa.poll().await;
+    assert_eq!(tasks.len(), 2);
+    assert!(matches!(tasks[0].task_type, TaskType::LinkStartMall));
+}
+
+#[tokio::test]
+async fn dialog_times_out() {
+    let config = Config {
+        dialog_timeout_secs: Some(1),
+        ..test_config()
+    };
+    let app = TestApp::start_with(&config, Arc::new(MemoryStore::new(DEFAULT_HISTORY_LIMIT))).await;
+    app.maa("device-1", "user-1").poll().await;
+    let maa = app.maa("device-1", "user-1");
+    let chat = app.chat();
+
+    chat.command("/appendtask").await;
+    let tasks = last(&app);
+    sleep(Duration::from_millis(1100)).await;
+
+    chat.press(1, &button(&tasks, "t:LinkStart-Combat")).await;
+    assert!(expired(&app), "the timed out dialog should be refused");
+    assert!(maa.poll().await.is_empty(), "no task should be queued");
+}
@@ -0,0 +1,104 @@
+use chrono::{Duration, Utc};
+use reqwest::Method;
+use serde_json::Value;
+
+use crate::model::TaskType;
+
+use super::TestApp;
+
+async fn app_with_history() -> TestApp {
+    let app = TestApp::start().await;
+    for device in ["device-1", "device-2"] {
+        let mut maa = app.maa(device, "user-1");
+        maa.poll().await;
+        app.app_state
+            .append_task(device, "user-1", &TaskType::LinkStartCombat)
+            .unwrap();
+        maa.run_once(|_| ("SUCCESS".to_owned(), String::new()))
+            .await;
+    }
+    app
+}
+
+async fn get_history(app: &TestApp, query: &[(&str, String)]) -> reqwest::Response {
+    app.api(Method::GET, "/api/history")
+        .query(query)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn history_endpoint_returns_json() {
+    let app = app_with_history().await;
+
+    let all: Vec<Value> = get_history(&app, &[]).await.json().await.unwrap();
+    assert_eq!(all.len(), 4);
+
+    let filtered: Vec<Value> = get_history(
+        &app,
+        &[
+            ("device", "device-2".to_owned()),
+            ("type", "LinkStart-Combat".to_owned()),
+        ],
+    )
+    .await
+    .json()
+    .await
+    .unwrap();
+    assert_eq!(filtered.len(), 1);
+    assert_eq!(filtered[0]["device"], "device-2");
+    assert_eq!(filtered[0]["status"], "SUCCESS");
+}
+
+#[tokio::test]
+async fn history_endpoint_filters_by_date() {
+    let app = app_with_history().await;
+    let hour_ago = (Utc::now() - Duration::hours(1)).to_rfc3339();
+
+    let recent: Vec<Value> = get_history(&app, &[("from", hour_ago.clone())])
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(recent.len(), 4);
+
+    let old: Vec<Value> = get_history(&app, &[("to", hour_ago)])
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert!(old.is_empty(), "nothing was reported an hour ago");
+}
+
+#[tokio::test]
+async fn history_endpoint_returns_csv() {
+    let app = app_with_history().await;
+
+    let response = get_history(&app, &[("format", "csv".to_owned())]).await;
+    assert_eq!(response.headers()["content-type"], "text/csv");
+
+    let body = response.text().await.unwrap();
+    let mut lines = body.lines();
+    assert!(
+        lines
+            .next()
+            .unwrap()
+            .starts_with("task_id,task_type,device,user"),
+        "csv should start with a header"
+    );
+    assert_eq!(lines.count(), 4);
+}
+
+#[tokio::test]
+async fn history_endpoint_rejects_bad_query() {
+    let app = app_with_history().await;
+
+    let response = get_history(&app, &[("from", "yesterday".to_owned())]).await;
+
+    assert!(
+        response.status().is_client_error(),
+        "unexpected status {}",
+        response.status()
+    );
+}
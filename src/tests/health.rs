@@ -0,0 +1,43 @@
+use reqwest::StatusCode;
+use serde_json::Value;
+
+use crate::health::check_telegram_once;
+
+use super::TestApp;
+
+async fn get(app: &TestApp, path: &str) -> (StatusCode, Value) {
+    let response = reqwest::get(format!("{}{path}", app.url))
+        .await
+        .expect("request sent");
+    let status = response.status();
+
+    (status, response.json().await.expect("json body"))
+}
+
+#[tokio::test]
+async fn healthz_reports_process_up() {
+    let app = TestApp::start().await;
+
+    let (status, body) = get(&app, "/healthz").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ok");
+}
+
+#[tokio::test]
+async fn readyz_waits_for_telegram() {
+    let app = TestApp::start().await;
+
+    let (status, body) = get(&app, "/readyz").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["ready"], false);
+    assert_eq!(body["state"]["ok"], true);
+    assert_eq!(body["telegram"]["ok"], false);
+    assert_eq!(body["store"]["ok"], true);
+
+    check_telegram_once(&app.app_state).await;
+
+    let (ready_status, ready_body) = get(&app, "/readyz").await;
+    assert_eq!(ready_status, StatusCode::OK);
+    assert_eq!(ready_body["ready"], true);
+    assert_eq!(ready_body["telegram"]["ok"], true);
+}
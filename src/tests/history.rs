@@ -0,0 +1,174 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+
+use crate::{
+    model::{ReportStatus, TaskType},
+    store::{HistoryFilter, MemoryStore},
+};
+
+use super::{test_config, TestApp};
+
+#[tokio::test]
+async fn reported_tasks_are_kept_in_history() {
+    let app = TestApp::start().await;
+    let mut first = app.maa("device-1", "user-1");
+    let mut second = app.maa("device-2", "user-1");
+    first.poll().await;
+    second.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartCombat)
+        .unwrap();
+    app.app_state
+        .append_task("device-2", "user-1", &TaskType::LinkStartBase)
+        .unwrap();
+    first
+        .run_once(|_| ("FAILED".to_owned(), String::new()))
+        .await;
+    second
+        .run_once(|_| ("SUCCESS".to_owned(), String::new()))
+        .await;
+
+    let store = app.app_state.store();
+    let all = store.history(&HistoryFilter::default()).unwrap();
+    assert_eq!(all.len(), 4, "every report should be recorded");
+    assert!(
+        matches!(all[0].task_type, TaskType::CaptureImage),
+        "history should be most recent first"
+    );
+    assert!(
+        all.iter().all(|e| e.fetched_at.is_some()),
+        "fetch time should be recorded"
+    );
+
+    let combat = store
+        .history(&HistoryFilter {
+            task_type: Some(TaskType::LinkStartCombat),
+            ..HistoryFilter::default()
+        })
+        .unwrap();
+    assert_eq!(combat.len(), 1);
+    assert_eq!(combat[0].device, "device-1");
+    assert_eq!(combat[0].status, ReportStatus::Failed);
+
+    let device = store
+        .history(&HistoryFilter {
+            device: Some("device-2".to_owned()),
+            ..HistoryFilter::default()
+        })
+        .unwrap();
+    assert_eq!(device.len(), 2);
+}
+
+#[tokio::test]
+async fn history_is_bounded() {
+    let app = TestApp::start_with(&test_config(), Arc::new(MemoryStore::new(3))).await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    for _ in 0..3 {
+        app.app_state
+            .append_task("device-1", "user-1", &TaskType::LinkStartMall)
+            .unwrap();
+    }
+    maa.run_once(|_| ("SUCCESS".to_owned(), String::new()))
+        .await;
+
+    let history = app
+        .app_state
+        .store()
+        .history(&HistoryFilter::default())
+        .unwrap();
+    assert_eq!(history.len(), 3, "oldest entries should be dropped");
+}
+
+#[tokio::test]
+async fn unknown_status_is_kept_as_reported() {
+    let app = TestApp::start().await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::CaptureImage)
+        .unwrap();
+    maa.run_once(|_| ("TIMEOUT".to_owned(), String::new()))
+        .await;
+
+    let history = app
+        .app_state
+        .store()
+        .history(&HistoryFilter::default())
+        .unwrap();
+    assert_eq!(history[0].status, ReportStatus::Other("TIMEOUT".to_owned()));
+    assert_eq!(
+        serde_json::to_value(&history[0]).unwrap()["status"],
+        "TIMEOUT",
+        "status should be serialized as reported"
+    );
+}
+
+/// Report `count` tasks of `task_type` from a device with a MAA-like 32 characters id.
+async fn report_tasks(app: &TestApp, task_type: &TaskType, count: usize) -> String {
+    let device = "0123456789abcdef0123456789abcdef";
+    let mut maa = app.maa(device, "user-1");
+    maa.poll().await;
+
+    for _ in 0..count {
+        app.app_state.append_task(device, "user-1", task_type).unwrap();
+    }
+    maa.run_once(|_| ("SUCCESS".to_owned(), String::new()))
+        .await;
+    app.notified().await;
+
+    device.to_owned()
+}
+
+/// Run `/history <args>` and turn to the second page, checking the size of every button.
+async fn turn_to_second_page(app: &TestApp, args: &str) {
+    let chat = app.chat();
+
+    chat.command(&format!("/history {args}")).await;
+    let requests = app.telegram.requests();
+    let first = requests.last().unwrap();
+    assert!(
+        first.text().unwrap().contains("page 1/2"),
+        "{:?}",
+        first.text()
+    );
+    let data = first.callback_data();
+    assert!(
+        data.iter().all(|d| d.len() <= 64),
+        "Telegram rejects callback data over 64 bytes: {data:?}"
+    );
+
+    // the mock numbers messages after the requests
+    let message_id = i32::try_from(requests.len()).unwrap();
+    chat.press(message_id, &data[0]).await;
+    let second = app.telegram.requests().pop().unwrap();
+    assert_eq!(second.method, "EditMessageText");
+    assert!(
+        second.text().unwrap().contains("page 2/2"),
+        "{:?}",
+        second.text()
+    );
+    assert!(second.callback_data().iter().all(|d| d.len() <= 64));
+}
+
+#[tokio::test]
+async fn history_pages_fit_in_callback_data() {
+    let app = TestApp::start().await;
+    let device = report_tasks(&app, &TaskType::LinkStartCombat, 6).await;
+
+    turn_to_second_page(&app, &format!("device={device} type=LinkStart-Combat")).await;
+}
+
+#[tokio::test]
+async fn date_filtered_history_pages_fit_in_callback_data() {
+    let app = TestApp::start().await;
+    report_tasks(&app, &TaskType::LinkStartMall, 3).await;
+
+    let from = (Utc::now() - chrono::Duration::hours(1)).to_rfc3339();
+    let to = (Utc::now() + chrono::Duration::hours(1)).to_rfc3339();
+    turn_to_second_page(&app, &format!("from={from} to={to}")).await;
+}
@@ -0,0 +1,53 @@
+use crate::model::TaskType;
+
+use super::TestApp;
+
+async fn scrape(app: &TestApp) -> String {
+    let response = reqwest::get(format!("{}/metrics", app.url))
+        .await
+        .expect("metrics request sent");
+    assert!(response.status().is_success());
+
+    response.text().await.expect("metrics body")
+}
+
+#[tokio::test]
+async fn metrics_track_polls_tasks_and_devices() {
+    let app = TestApp::start().await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartCombat)
+        .unwrap();
+
+    let queued = scrape(&app).await;
+    assert!(queued.contains(r#"maa_tgbot_polls_total{device="device-1"} 1"#));
+    assert!(queued.contains(r#"maa_tgbot_tasks_enqueued_total{type="LinkStart-Combat"} 1"#));
+    assert!(queued.contains(r#"maa_tgbot_tasks_enqueued_total{type="CaptureImage"} 1"#));
+    assert!(queued.contains(r#"maa_tgbot_queue_length{device="device-1"} 2"#));
+    assert!(queued.contains(r#"maa_tgbot_device_online{device="device-1"} 1"#));
+
+    maa.run_once(|task| {
+        let status = if matches!(task.task_type, TaskType::CaptureImage) {
+            "FAILED"
+        } else {
+            "SUCCESS"
+        };
+        (status.to_owned(), String::new())
+    })
+    .await;
+
+    let reported = scrape(&app).await;
+    assert!(reported.contains(r#"maa_tgbot_polls_total{device="device-1"} 2"#));
+    assert!(reported
+        .contains(r#"maa_tgbot_tasks_reported_total{status="SUCCESS",type="LinkStart-Combat"} 1"#));
+    assert!(reported
+        .contains(r#"maa_tgbot_tasks_reported_total{status="FAILED",type="CaptureImage"} 1"#));
+    assert!(
+        reported.contains(r#"maa_tgbot_task_report_latency_seconds_count{type="CaptureImage"} 1"#)
+    );
+    assert!(reported.contains(r#"maa_tgbot_task_run_seconds_count{type="LinkStart-Combat"} 1"#));
+    assert!(reported.contains(r#"maa_tgbot_queue_length{device="device-1"} 0"#));
+    assert!(reported.contains("maa_tgbot_telegram_api_errors_total 0"));
+}
@@ -0,0 +1,260 @@
+use std::{
+    future::pending,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{extract::State, routing::post, Json, Router};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use serde_json::{json, Value};
+use tokio::time::{sleep, timeout};
+
+use crate::{
+    config::{Config, WebhookConfig, DEFAULT_HISTORY_LIMIT},
+    error::AppError,
+    model::TaskType,
+    outbox::RetryPolicy,
+    store::MemoryStore,
+};
+
+use super::{serve, test_config, TestApp};
+
+type Received = Arc<Mutex<Vec<Value>>>;
+
+async fn start_webhook() -> (String, Received) {
+    let received = Received::default();
+
+    let app = Router::new()
+        .route(
+            "/hook",
+            post(
+                |State(sink): State<Received>, Json(body): Json<Value>| async move {
+                    sink.lock().unwrap().push(body);
+                },
+            ),
+        )
+        .with_state(Arc::clone(&received));
+
+    (format!("{}/hook", serve(app).await), received)
+}
+
+#[tokio::test]
+async fn reports_are_posted_to_webhooks() {
+    let (with_image, received_with_image) = start_webhook().await;
+    let (without_image, received_without_image) = start_webhook().await;
+    let config = Config {
+        webhooks: Some(vec![
+            WebhookConfig {
+                url: with_image,
+                include_image: true,
+            },
+            WebhookConfig {
+                url: without_image,
+                include_image: false,
+            },
+        ]),
+        ..test_config()
+    };
+    let app = TestApp::start_with(&config, Arc::new(MemoryStore::new(DEFAULT_HISTORY_LIMIT))).await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::CaptureImageNow)
+        .unwrap();
+    let image = BASE64_STANDARD.encode(b"image");
+    maa.run_once(|task| {
+        let status = if matches!(task.task_type, TaskType::CaptureImage) {
+            "FAILED"
+        } else {
+            "SUCCESS"
+        };
+        (status.to_owned(), image.clone())
+    })
+    .await;
+    app.notified().await;
+
+    let received = received_with_image.lock().unwrap().clone();
+    assert_eq!(received.len(), 2);
+    assert_eq!(received[0]["type"], "CaptureImageNow");
+    assert_eq!(received[0]["device"], "device-1");
+    assert_eq!(received[0]["status"], "SUCCESS");
+    assert_eq!(received[0]["image"], image.as_str());
+    assert_eq!(received[1]["status"], "FAILED");
+
+    let received_plain = received_without_image.lock().unwrap().clone();
+    assert_eq!(received_plain.len(), 2);
+    assert!(
+        received_plain[0].get("image").is_none(),
+        "image should be left out"
+    );
+
+    assert_eq!(
+        app.telegram.methods(),
+        vec!["SendMessage", "SendPhoto", "SendMessage", "SendPhoto"],
+        "telegram should still be notified"
+    );
+}
+
+#[tokio::test]
+async fn unanswered_webhook_does_not_hold_back_telegram() {
+    let hanging = Router::new().route("/hook", post(pending::<()>));
+    let config = Config {
+        webhooks: Some(vec![WebhookConfig {
+            url: format!("{}/hook", serve(hanging).await),
+            include_image: false,
+        }]),
+        ..test_config()
+    };
+    let app = TestApp::start_with(&config, Arc::new(MemoryStore::new(DEFAULT_HISTORY_LIMIT))).await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    for task_type in [TaskType::LinkStartCombat, TaskType::LinkStartMall] {
+        app.app_state
+            .append_task("device-1", "user-1", &task_type)
+            .unwrap();
+    }
+    maa.run_once(|_| ("SUCCESS".to_owned(), String::new()))
+        .await;
+
+    timeout(Duration::from_secs(3), async {
+        while app.telegram.texts().len() < 4 {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("every report should reach Telegram while the webhook hangs");
+    assert!(
+        app.app_state.outbox().pending() > 0,
+        "the webhook should still be waited for"
+    );
+}
+
+#[tokio::test]
+async fn failed_capture_sends_no_photo() {
+    let app = TestApp::start().await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::CaptureImage)
+        .unwrap();
+    maa.run_once(|_| ("FAILED".to_owned(), String::new())).await;
+    app.notified().await;
+
+    assert_eq!(app.telegram.methods(), vec!["SendMessage"]);
+}
+
+#[tokio::test]
+async fn report_is_accepted_when_telegram_fails() {
+    let app = TestApp::start().await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::CaptureImage)
+        .unwrap();
+    app.telegram.fail_next(json!({
+        "error_code": 400,
+        "description": "Bad Request: chat not found",
+    }));
+    maa.run_once(|_| ("FAILED".to_owned(), String::new())).await;
+    app.notified().await;
+
+    assert_eq!(
+        app.telegram.methods(),
+        vec!["SendMessage"],
+        "errors other than rate limits should not be retried"
+    );
+}
+
+#[tokio::test]
+async fn rate_limited_notification_is_retried() {
+    let app = TestApp::start().await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::CaptureImage)
+        .unwrap();
+    app.telegram.fail_next(json!({
+        "error_code": 429,
+        "description": "Too Many Requests: retry after 1",
+        "parameters": { "retry_after": 1 },
+    }));
+    maa.run_once(|_| ("FAILED".to_owned(), String::new())).await;
+    app.notified().await;
+
+    assert_eq!(
+        app.telegram.texts(),
+        vec![
+            "Task CaptureImage finished. Status: FAILED",
+            "Task CaptureImage finished. Status: FAILED",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn rate_limited_photo_is_retried_alone() {
+    let app = TestApp::start().await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::CaptureImage)
+        .unwrap();
+    app.telegram.fail_next_of(
+        "SendPhoto",
+        json!({
+            "error_code": 429,
+            "description": "Too Many Requests: retry after 0",
+            "parameters": { "retry_after": 0 },
+        }),
+    );
+    let image = BASE64_STANDARD.encode(b"image");
+    maa.run_once(|_| ("SUCCESS".to_owned(), image.clone())).await;
+    app.notified().await;
+
+    assert_eq!(
+        app.telegram.methods(),
+        vec!["SendMessage", "SendPhoto", "SendPhoto"],
+        "the message should not be sent again"
+    );
+    assert_eq!(
+        app.telegram.texts(),
+        vec!["Task CaptureImage finished. Status: SUCCESS"]
+    );
+}
+
+#[test]
+fn retry_backoff_doubles_up_to_the_limit() {
+    let retry = RetryPolicy {
+        max_attempts: 5,
+        initial_backoff: Duration::from_secs(1),
+        max_backoff: Duration::from_secs(3),
+    };
+    let network = AppError::TelegramNetworkError("connection reset".to_owned());
+
+    let delays: Vec<_> = (1..=5).map(|attempt| retry.delay(attempt, &network)).collect();
+    assert_eq!(
+        delays,
+        vec![
+            Some(Duration::from_secs(1)),
+            Some(Duration::from_secs(2)),
+            Some(Duration::from_secs(3)),
+            Some(Duration::from_secs(3)),
+            None,
+        ]
+    );
+
+    assert_eq!(
+        retry.delay(1, &AppError::TelegramRetryAfter(30)),
+        Some(Duration::from_secs(30)),
+        "the delay asked by Telegram should be respected"
+    );
+    assert_eq!(
+        retry.delay(1, &AppError::TeloxideError("chat not found".to_owned())),
+        None
+    );
+}
@@ -0,0 +1,144 @@
+use crate::{
+    error::AppError,
+    model::{Task, TaskType},
+    store::Move,
+};
+
+use super::TestApp;
+
+fn types(tasks: &[Task]) -> Vec<String> {
+    tasks.iter().map(|t| t.task_type.to_string()).collect()
+}
+
+#[tokio::test]
+async fn urgent_tasks_jump_the_queue() {
+    let app = TestApp::start().await;
+    let maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    for task_type in [
+        TaskType::LinkStartCombat,
+        TaskType::CaptureImageNow,
+        TaskType::LinkStartMall,
+        TaskType::StopTask,
+    ] {
+        app.app_state
+            .append_task("device-1", "user-1", &task_type)
+            .unwrap();
+    }
+
+    assert_eq!(
+        types(&maa.poll().await),
+        vec![
+            "CaptureImageNow",
+            "CaptureImage",
+            "StopTask",
+            "CaptureImage",
+            "LinkStart-Combat",
+            "CaptureImage",
+            "LinkStart-Mall",
+            "CaptureImage",
+        ],
+        "urgent tasks should come first, in the order they were queued"
+    );
+}
+
+#[tokio::test]
+async fn moved_tasks_are_fetched_in_the_new_order() {
+    let app = TestApp::start().await;
+    let maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    let mut ids = vec![];
+    for task_type in [
+        TaskType::LinkStartCombat,
+        TaskType::LinkStartMall,
+        TaskType::LinkStartBase,
+    ] {
+        let tasks = app
+            .app_state
+            .append_task("device-1", "user-1", &task_type)
+            .unwrap();
+        ids.push(tasks[0].id.clone());
+    }
+    let store = app.app_state.store();
+
+    store.move_task(&ids[2], Move::Front).unwrap();
+    store.move_task(&ids[0], Move::Down).unwrap();
+    store.move_task(&ids[1], Move::Up).unwrap();
+    // already first, nothing to do
+    store.move_task(&ids[1], Move::Up).unwrap();
+
+    assert_eq!(
+        types(&maa.poll().await),
+        vec![
+            "LinkStart-Mall",
+            "CaptureImage",
+            "LinkStart-Base",
+            "CaptureImage",
+            "LinkStart-Combat",
+            "CaptureImage",
+        ],
+        "screenshots should move along with their task"
+    );
+    let queue = store.list().unwrap()[0].users["user-1"].tasks.clone();
+    for (i, id) in [(0, &ids[1]), (2, &ids[2]), (4, &ids[0])] {
+        assert_eq!(&queue[i].id, id);
+        let capture = store.get(&queue[i + 1].id).unwrap();
+        assert_eq!(capture.capture_of.as_ref(), Some(id));
+    }
+}
+
+#[tokio::test]
+async fn delivered_tasks_are_not_moved() {
+    let app = TestApp::start().await;
+    let maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    let combat = app
+        .app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartCombat)
+        .unwrap();
+    maa.poll().await;
+    let mall = app
+        .app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartMall)
+        .unwrap();
+    let store = app.app_state.store();
+
+    assert!(matches!(
+        store.move_task(&combat[0].id, Move::Down),
+        Err(AppError::InvalidArgument(_))
+    ));
+    store.move_task(&mall[0].id, Move::Front).unwrap();
+    let queue = store.list().unwrap()[0].users["user-1"].tasks.clone();
+    assert_eq!(
+        types(&queue),
+        vec![
+            "LinkStart-Combat",
+            "CaptureImage",
+            "LinkStart-Mall",
+            "CaptureImage"
+        ],
+        "tasks handed to MAA should stay first"
+    );
+
+    let chat = app.chat();
+    chat.command("/queue").await;
+    let shown = app.telegram.requests().last().cloned().unwrap();
+    assert!(
+        shown
+            .text()
+            .unwrap()
+            .contains("1. LinkStart-Combat (handed to MAA)"),
+        "{:?}",
+        shown.text()
+    );
+    let buttons = shown.callback_data();
+    assert_eq!(
+        buttons.len(),
+        3,
+        "only the undelivered task should be movable, its screenshot along with it"
+    );
+    assert!(buttons.iter().all(|data| !data.ends_with(&combat[0].id)));
+}
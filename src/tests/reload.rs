@@ -0,0 +1,123 @@
+use std::{
+    env::temp_dir,
+    fs::{remove_file, write},
+    sync::Arc,
+};
+
+use serde_json::json;
+
+use crate::{
+    config::{Config, DeviceInfo, DEFAULT_HISTORY_LIMIT},
+    model::TaskType,
+    reload::reload_and_report,
+    state::AppState,
+    store::{MemoryStore, TaskStore},
+};
+
+use super::{test_config, MockTelegram, TG_USER_ID};
+
+fn write_config(path: &str, devices: &serde_json::Value) {
+    let config = json!({
+        "port": 0,
+        "telegram_bot_token": "test-token",
+        "telegram_user_id": TG_USER_ID,
+        "devices": devices,
+    });
+    write(path, config.to_string()).unwrap();
+}
+
+#[tokio::test]
+async fn reload_applies_allow_list_and_names() {
+    let path = temp_dir()
+        .join(format!("maa-tgbot-{}.json", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .into_owned();
+    let config = Config {
+        devices: Some(vec![DeviceInfo {
+            id: "device-1".to_owned(),
+            name: "Phone".to_owned(),
+        }]),
+        ..test_config()
+    };
+    let telegram = MockTelegram::start().await;
+    let store = Arc::new(MemoryStore::new(DEFAULT_HISTORY_LIMIT));
+    let app_state = AppState::new(
+        &config,
+        telegram.bot(),
+        Arc::clone(&store) as Arc<dyn TaskStore>,
+    )
+    .with_config_file(&path);
+
+    app_state.poll("device-1", "user-1").unwrap();
+    app_state.poll("device-2", "user-1").unwrap();
+    assert_eq!(
+        app_state
+            .devices()
+            .unwrap()
+            .iter()
+            .map(|d| d.id.as_str())
+            .collect::<Vec<_>>(),
+        vec!["device-1"]
+    );
+
+    write_config(
+        &path,
+        &json!([{ "id": "device-1", "name": "Tablet" }, { "id": "device-2", "name": "PC" }]),
+    );
+    reload_and_report(&app_state).await.unwrap();
+
+    let texts = telegram.texts();
+    assert_eq!(texts.len(), 1);
+    assert!(texts[0].starts_with("Config reloaded:"), "{}", texts[0]);
+    assert!(texts[0].contains("Device device-1 renamed from Phone to Tablet"));
+    assert!(texts[0].contains("Device device-2 (PC) allowed"));
+    assert_eq!(
+        app_state
+            .devices()
+            .unwrap()
+            .iter()
+            .map(|d| d.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["Tablet"]
+    );
+
+    app_state.poll("device-2", "user-1").unwrap();
+    app_state
+        .append_task("device-2", "user-1", &TaskType::LinkStartCombat)
+        .unwrap();
+    assert_eq!(app_state.poll("device-2", "user-1").unwrap().len(), 2);
+
+    remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn invalid_config_is_rejected() {
+    let path = temp_dir()
+        .join(format!("maa-tgbot-{}.json", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .into_owned();
+    let telegram = MockTelegram::start().await;
+    let app_state = AppState::new(
+        &test_config(),
+        telegram.bot(),
+        Arc::new(MemoryStore::new(DEFAULT_HISTORY_LIMIT)),
+    )
+    .with_config_file(&path);
+
+    write(&path, "{ not json").unwrap();
+    reload_and_report(&app_state).await.unwrap();
+
+    let texts = telegram.texts();
+    assert_eq!(texts.len(), 1);
+    assert!(
+        texts[0].starts_with("Config reload rejected"),
+        "{}",
+        texts[0]
+    );
+    assert!(
+        app_state.is_admin_token(super::ADMIN_TOKEN).unwrap(),
+        "config kept"
+    );
+
+    remove_file(&path).unwrap();
+}
@@ -0,0 +1,285 @@
+use std::sync::Arc;
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+
+use crate::{
+    config::{Config, DeviceInfo, DEFAULT_HISTORY_LIMIT},
+    model::{ReportStatus, Task, TaskType},
+    store::{HistoryFilter, MemoryStore},
+};
+
+use super::{test_config, TestApp};
+
+fn success(_task: &Task) -> (String, String) {
+    ("SUCCESS".to_owned(), String::new())
+}
+
+#[tokio::test]
+async fn poll_registers_device_and_user() {
+    let app = TestApp::start().await;
+    let maa = app.maa("device-1", "user-1");
+
+    assert!(
+        maa.poll().await.is_empty(),
+        "new device should have no tasks"
+    );
+
+    assert_eq!(
+        app.app_state
+            .devices()
+            .unwrap()
+            .iter()
+            .map(|d| d.id.as_str())
+            .collect::<Vec<_>>(),
+        vec!["device-1"]
+    );
+    assert_eq!(app.app_state.users("device-1").unwrap(), vec!["user-1"]);
+    assert!(
+        app.app_state.is_single_user().unwrap(),
+        "only one user registered"
+    );
+}
+
+#[tokio::test]
+async fn device_outside_allow_list_gets_nothing() {
+    let config = Config {
+        devices: Some(vec![DeviceInfo {
+            id: "device-1".to_owned(),
+            name: "Phone".to_owned(),
+        }]),
+        ..test_config()
+    };
+    let app = TestApp::start_with(&config, Arc::new(MemoryStore::new(DEFAULT_HISTORY_LIMIT))).await;
+
+    app.maa("device-2", "user-1").poll().await;
+    app.maa("device-1", "user-1").poll().await;
+
+    assert_eq!(
+        app.app_state
+            .devices()
+            .unwrap()
+            .iter()
+            .map(|d| d.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["Phone"]
+    );
+}
+
+#[tokio::test]
+async fn finished_task_is_notified_with_screenshot() {
+    let app = TestApp::start().await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartCombat)
+        .unwrap();
+
+    let image = BASE64_STANDARD.encode(b"not really a png");
+    let tasks = maa
+        .run_once(|task| {
+            let payload = if matches!(task.task_type, TaskType::CaptureImage) {
+                image.clone()
+            } else {
+                String::new()
+            };
+            ("SUCCESS".to_owned(), payload)
+        })
+        .await;
+    app.notified().await;
+
+    assert_eq!(tasks.len(), 2, "task should be followed by a screenshot");
+    assert_eq!(
+        app.telegram.methods(),
+        vec!["SendMessage", "SendMessage", "SendPhoto"]
+    );
+    assert_eq!(
+        app.telegram.texts(),
+        vec![
+            "Task LinkStart-Combat finished. Status: SUCCESS",
+            "Task CaptureImage finished. Status: SUCCESS",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn heartbeat_reports_running_task() {
+    let app = TestApp::start().await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartMall)
+        .unwrap();
+    let running = maa.poll_new().await[0].id.clone();
+
+    for _ in 0..2 {
+        app.app_state
+            .append_task("device-1", "user-1", &TaskType::HeartBeat)
+            .unwrap();
+    }
+    let heartbeats: Vec<_> = maa
+        .poll_new()
+        .await
+        .into_iter()
+        .filter(|t| matches!(t.task_type, TaskType::HeartBeat))
+        .collect();
+
+    assert!(
+        maa.report(&heartbeats[0].id, "SUCCESS", &running)
+            .await
+            .is_success(),
+        "heartbeat report should be accepted"
+    );
+    assert!(
+        maa.report(&heartbeats[1].id, "SUCCESS", "")
+            .await
+            .is_success(),
+        "empty heartbeat report should be accepted"
+    );
+    app.notified().await;
+
+    let texts = app.telegram.texts();
+    assert_eq!(
+        texts[1],
+        format!("Task LinkStart-Mall is running.\nTask id: {running}")
+    );
+    assert_eq!(texts[3], "No task is running.");
+}
+
+#[tokio::test]
+async fn screenshot_all_reaches_every_user() {
+    let app = TestApp::start().await;
+    let mut first = app.maa("device-1", "user-1");
+    let mut second = app.maa("device-2", "user-2");
+    first.poll().await;
+    second.poll().await;
+
+    let count = app
+        .app_state
+        .append_task_to_all(&TaskType::CaptureImageNow)
+        .unwrap();
+    assert_eq!(count, 2);
+
+    for maa in [&mut first, &mut second] {
+        let tasks = maa.run_once(success).await;
+        assert_eq!(tasks.len(), 2, "screenshot should be followed by a capture");
+        assert!(
+            matches!(tasks[0].task_type, TaskType::CaptureImageNow),
+            "screenshot task should come first"
+        );
+    }
+}
+
+#[tokio::test]
+async fn report_for_unknown_task_fails() {
+    let app = TestApp::start().await;
+    let maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    let code = maa.report("no-such-task", "SUCCESS", "").await;
+
+    assert_eq!(code, reqwest::StatusCode::NOT_FOUND);
+    assert!(app.telegram.requests().is_empty(), "nothing should be sent");
+}
+
+#[tokio::test]
+async fn repeated_report_is_notified_once() {
+    let app = TestApp::start().await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::CaptureImage)
+        .unwrap();
+    let task = maa.poll_new().await.remove(0);
+
+    let (first, second) = tokio::join!(
+        maa.report(&task.id, "SUCCESS", ""),
+        maa.report(&task.id, "SUCCESS", "")
+    );
+    let retried = maa.report(&task.id, "SUCCESS", "").await;
+    app.notified().await;
+
+    for code in [first, second, retried] {
+        assert!(code.is_success(), "duplicate report should be accepted: {code}");
+    }
+    assert_eq!(app.telegram.methods(), vec!["SendMessage"]);
+    assert_eq!(
+        app.app_state
+            .store()
+            .history(&HistoryFilter::default())
+            .unwrap()
+            .len(),
+        1,
+        "the task should be in the history once"
+    );
+}
+
+#[tokio::test]
+async fn conflicting_report_keeps_the_first_one() {
+    let app = TestApp::start().await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::CaptureImage)
+        .unwrap();
+    let task = maa.poll_new().await.remove(0);
+
+    assert!(maa.report(&task.id, "SUCCESS", "").await.is_success(), "first report");
+    let code = maa.report(&task.id, "FAILED", "").await;
+    app.notified().await;
+
+    assert!(code.is_success(), "conflicting report should be accepted: {code}");
+    assert_eq!(
+        app.telegram.texts(),
+        vec!["Task CaptureImage finished. Status: SUCCESS"]
+    );
+    assert_eq!(
+        app.app_state.store().reported(&task.id).unwrap().unwrap().status,
+        ReportStatus::Success
+    );
+}
+
+#[tokio::test]
+async fn report_from_another_device_is_rejected() {
+    let app = TestApp::start().await;
+    let mut owner = app.maa("device-1", "user-1");
+    let other = app.maa("device-2", "user-1");
+    owner.poll().await;
+    other.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::CaptureImage)
+        .unwrap();
+    let task = owner.poll_new().await.remove(0);
+
+    assert_eq!(
+        other.report(&task.id, "SUCCESS", "").await,
+        reqwest::StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        app.maa("device-3", "user-1")
+            .report(&task.id, "SUCCESS", "")
+            .await,
+        reqwest::StatusCode::NOT_FOUND,
+        "unknown device"
+    );
+    assert!(
+        app.app_state.store().get(&task.id).is_ok(),
+        "task should still wait for its report"
+    );
+
+    assert!(
+        owner.report(&task.id, "SUCCESS", "").await.is_success(),
+        "owner report should be accepted"
+    );
+    assert_eq!(
+        other.report(&task.id, "SUCCESS", "").await,
+        reqwest::StatusCode::FORBIDDEN,
+        "repeated report should be checked too"
+    );
+    app.notified().await;
+    assert_eq!(app.telegram.methods(), vec!["SendMessage"]);
+}
@@ -0,0 +1,162 @@
+use std::sync::Arc;
+
+use serde_json::Value;
+
+use crate::{
+    config::{Config, TaskRetryConfig, DEFAULT_HISTORY_LIMIT},
+    model::{Task, TaskType},
+    store::MemoryStore,
+};
+
+use super::{test_config, SentRequest, TestApp};
+
+async fn start_with_retries(backoff_secs: u64) -> TestApp {
+    let config = Config {
+        task_retries: Some(vec![TaskRetryConfig {
+            task_type: TaskType::LinkStartCombat,
+            max_attempts: 2,
+            backoff_secs,
+        }]),
+        ..test_config()
+    };
+
+    TestApp::start_with(&config, Arc::new(MemoryStore::new(DEFAULT_HISTORY_LIMIT))).await
+}
+
+fn combat_fails(task: &Task) -> (String, String) {
+    let status = if matches!(task.task_type, TaskType::LinkStartCombat) {
+        "FAILED"
+    } else {
+        "SUCCESS"
+    };
+    (status.to_owned(), String::new())
+}
+
+#[tokio::test]
+async fn failed_task_is_retried_up_to_max_attempts() {
+    let app = start_with_retries(0).await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartCombat)
+        .unwrap();
+    maa.run_once(combat_fails).await;
+    let retried = maa.run_once(combat_fails).await;
+    let after_last = maa.run_once(combat_fails).await;
+    app.notified().await;
+
+    assert_eq!(retried.len(), 2, "task and capture should be queued again");
+    assert!(
+        matches!(retried[0].task_type, TaskType::LinkStartCombat),
+        "failed task should come first"
+    );
+    assert!(after_last.is_empty(), "no attempts should be left");
+
+    let texts = app.telegram.texts();
+    assert_eq!(
+        texts[0],
+        "Task LinkStart-Combat finished. Status: FAILED (attempt 1/2)\nRetrying in 0s."
+    );
+    assert_eq!(
+        texts[2],
+        "Task LinkStart-Combat finished. Status: FAILED (attempt 2/2)\nNo attempts left."
+    );
+
+    let requests = app.telegram.requests();
+    let markups: Vec<_> = requests
+        .iter()
+        .filter_map(|r| serde_json::from_slice::<Value>(&r.body).ok())
+        .filter_map(|body| body.get("reply_markup").cloned())
+        .collect();
+    assert_eq!(markups.len(), 1, "only the last failure should offer to retry");
+    assert_eq!(
+        markups[0]["inline_keyboard"][0][0]["callback_data"],
+        format!("r:{}", retried[0].id)
+    );
+}
+
+#[tokio::test]
+async fn retry_waits_for_the_backoff() {
+    let app = start_with_retries(60).await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartCombat)
+        .unwrap();
+    maa.run_once(combat_fails).await;
+
+    assert!(
+        maa.poll_new().await.is_empty(),
+        "retry should be held back"
+    );
+    let queued = app.app_state.store().list().unwrap()[0].users["user-1"]
+        .tasks
+        .clone();
+    assert_eq!(queued.len(), 2, "retry should be queued");
+    assert_eq!(app.app_state.store().get(&queued[0].id).unwrap().attempt, 2);
+}
+
+#[tokio::test]
+async fn oversized_backoff_holds_the_retry_back() {
+    let app = start_with_retries(u64::MAX).await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartCombat)
+        .unwrap();
+    maa.run_once(combat_fails).await;
+
+    assert!(maa.poll_new().await.is_empty(), "retry should be held back");
+    let queued = app.app_state.store().list().unwrap()[0].users["user-1"]
+        .tasks
+        .clone();
+    assert_eq!(queued.len(), 2, "retry should be queued");
+}
+
+#[tokio::test]
+async fn retry_button_queues_the_task_once_with_fresh_attempts() {
+    let app = start_with_retries(0).await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartCombat)
+        .unwrap();
+    maa.run_once(combat_fails).await;
+    maa.run_once(combat_fails).await;
+    app.notified().await;
+
+    let retry = app
+        .telegram
+        .requests()
+        .iter()
+        .flat_map(SentRequest::callback_data)
+        .find(|data| data.starts_with("r:"))
+        .expect("the last failure should offer to retry");
+    let chat = app.chat();
+    chat.press(1, &retry).await;
+    // a double tap
+    chat.press(1, &retry).await;
+
+    let texts = app.telegram.texts();
+    assert_eq!(
+        texts[texts.len() - 2..],
+        [
+            "Task LinkStart-Combat queued again.",
+            "The task was already queued again.",
+        ]
+    );
+
+    let queued = maa.run_once(combat_fails).await;
+    assert_eq!(queued.len(), 2, "task and capture should be queued once");
+    app.notified().await;
+    let after_retry = app.telegram.texts();
+    assert_eq!(
+        after_retry[texts.len()],
+        "Task LinkStart-Combat finished. Status: FAILED (attempt 1/2)\nRetrying in 0s.",
+        "attempts should be counted from the first one again"
+    );
+}
@@ -0,0 +1,81 @@
+use std::{
+    env::temp_dir,
+    fs::{read_to_string, remove_file},
+    sync::Arc,
+};
+
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    config::DEFAULT_HISTORY_LIMIT,
+    model::{ReportStatus, TaskType},
+    notify::TaskReport,
+    outbox,
+    state::AppState,
+    store::{FileStore, MemoryStore, TaskStore},
+};
+
+use super::{test_config, MockTelegram, TestApp, TEST_RETRY_POLICY};
+
+#[tokio::test]
+async fn queued_notifications_are_sent_on_shutdown() {
+    let telegram = MockTelegram::start().await;
+    let app_state = Arc::new(AppState::new(
+        &test_config(),
+        telegram.bot(),
+        Arc::new(MemoryStore::new(DEFAULT_HISTORY_LIMIT)),
+    ));
+    let shutdown = CancellationToken::new();
+    let outbox_run = tokio::spawn(outbox::run(
+        Arc::clone(&app_state),
+        TEST_RETRY_POLICY,
+        shutdown.clone(),
+    ));
+
+    for task_type in [TaskType::LinkStartCombat, TaskType::LinkStartMall] {
+        app_state
+            .notify(TaskReport {
+                task_id: uuid::Uuid::new_v4().to_string(),
+                task_type,
+                device: "device-1".to_owned(),
+                user: "user-1".to_owned(),
+                status: ReportStatus::Success,
+                image: None,
+                running_task: None,
+                attempt: None,
+            })
+            .unwrap();
+    }
+    shutdown.cancel();
+    outbox_run.await.unwrap();
+
+    assert_eq!(app_state.outbox().pending(), 0);
+    let texts = telegram.texts();
+    assert_eq!(
+        texts.len(),
+        2,
+        "every report should be notified once: {texts:?}"
+    );
+}
+
+#[tokio::test]
+async fn state_is_flushed_on_shutdown() {
+    let path = temp_dir().join(format!("maa-tgbot-{}.json", uuid::Uuid::new_v4()));
+    let store = FileStore::open(path.to_str().unwrap(), DEFAULT_HISTORY_LIMIT).unwrap();
+    let app = TestApp::start_with(&test_config(), Arc::new(store) as Arc<dyn TaskStore>).await;
+    let mut maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartCombat)
+        .unwrap();
+    maa.run_once(|_| ("SUCCESS".to_owned(), String::new()))
+        .await;
+    remove_file(&path).unwrap();
+
+    app.shut_down().await.unwrap();
+
+    let state = read_to_string(&path).expect("state should be written on shutdown");
+    assert!(state.contains("LinkStart-Combat"), "{state}");
+    remove_file(&path).unwrap();
+}
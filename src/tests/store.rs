@@ -0,0 +1,68 @@
+use std::{
+    env::temp_dir,
+    fs::{create_dir_all, remove_dir_all, remove_file},
+    sync::Arc,
+};
+
+use crate::{
+    config::DEFAULT_HISTORY_LIMIT,
+    model::TaskType,
+    store::{FileStore, TaskStore},
+};
+
+use super::{test_config, TestApp};
+
+#[tokio::test]
+async fn file_store_survives_restart() {
+    let path = temp_dir().join(format!("maa-tgbot-{}.json", uuid::Uuid::new_v4()));
+    let path = path.to_str().unwrap();
+
+    let app = TestApp::start_with(&test_config(), Arc::new(FileStore::open(path, DEFAULT_HISTORY_LIMIT).unwrap())).await;
+    app.maa("device-1", "user-1").poll().await;
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartBase)
+        .unwrap();
+
+    let restarted =
+        TestApp::start_with(&test_config(), Arc::new(FileStore::open(path, DEFAULT_HISTORY_LIMIT).unwrap())).await;
+    let tasks = restarted.maa("device-1", "user-1").poll().await;
+
+    assert_eq!(tasks.len(), 2, "queued tasks should be restored");
+    assert!(
+        matches!(tasks[0].task_type, TaskType::LinkStartBase),
+        "queue order should be kept"
+    );
+
+    remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn failed_write_keeps_the_change_and_is_written_later() {
+    let dir = temp_dir().join(format!("maa-tgbot-{}", uuid::Uuid::new_v4()));
+    create_dir_all(&dir).unwrap();
+    let path = dir.join("state.json");
+    let path = path.to_str().unwrap();
+
+    let store = Arc::new(FileStore::open(path, DEFAULT_HISTORY_LIMIT).unwrap());
+    let app = TestApp::start_with(&test_config(), store).await;
+    let maa = app.maa("device-1", "user-1");
+    maa.poll().await;
+
+    remove_dir_all(&dir).unwrap();
+    app.app_state
+        .append_task("device-1", "user-1", &TaskType::LinkStartBase)
+        .expect("the task should be queued even if it cannot be written");
+
+    create_dir_all(&dir).unwrap();
+    let tasks = maa.poll().await;
+    assert_eq!(tasks.len(), 2, "the task should be handed to MAA");
+
+    let reopened = FileStore::open(path, DEFAULT_HISTORY_LIMIT).unwrap();
+    assert_eq!(
+        reopened.list().unwrap()[0].users["user-1"].tasks.len(),
+        2,
+        "the next poll should write the state again"
+    );
+
+    remove_dir_all(&dir).unwrap();
+}